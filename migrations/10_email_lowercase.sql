@@ -0,0 +1,9 @@
+-- Emails are stored trimmed and in lowercase, and looked up so, but rows written before
+-- the normalization may still be mixed-case. If two users not deleted then share an email,
+-- the unique index fails, and one of them needs to be changed or deleted first.
+
+drop index usr_email_key;
+
+update usr set email = lower(trim(email)) where email <> lower(trim(email));
+
+create unique index usr_email_key on usr (lower(email)) where deleted_at is null;
@@ -0,0 +1,7 @@
+-- Soft delete: deleted users are only marked as such, until purged.
+
+alter table usr add column deleted_at timestamptz;
+
+-- Emails need only be unique among the users not deleted.
+alter table usr drop constraint usr_email_key;
+create unique index usr_email_key on usr (email) where deleted_at is null;
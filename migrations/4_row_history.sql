@@ -0,0 +1,47 @@
+-- Generic change history for the tables registered with `trigger_row_history`.
+
+create table row_history
+(
+    history_id    bigint generated always as identity primary key,
+    table_name    text        not null,
+    row_id        text        not null,
+    operation     text        not null,
+    old_row       jsonb,
+    new_row       jsonb,
+    -- the application can identify the user on whose behalf it acts with `set local request.user_id`
+    changed_by    text        not null default coalesce(nullif(current_setting('request.user_id', true), ''), current_user),
+    changed_at    timestamptz not null default now()
+);
+
+create index row_history_row_idx on row_history (table_name, row_id, changed_at);
+
+create or replace function record_row_history()
+    returns trigger as
+$$
+declare
+    old_row jsonb := case when TG_OP <> 'INSERT' then to_jsonb(OLD) end;
+    new_row jsonb := case when TG_OP <> 'DELETE' then to_jsonb(NEW) end;
+begin
+    if old_row = new_row then
+        return null;
+    end if;
+    insert into row_history (table_name, row_id, operation, old_row, new_row)
+    values (TG_TABLE_NAME, coalesce(new_row, old_row) ->> TG_ARGV[0], lower(TG_OP), old_row, new_row);
+    return null;
+end;
+$$ language plpgsql;
+
+-- Registers the table, whose rows are identified by the given key column.
+create or replace function trigger_row_history(tablename regclass, key_column text)
+    returns void as
+$$
+begin
+    execute format('CREATE TRIGGER record_row_history
+        AFTER INSERT OR UPDATE OR DELETE
+        ON %s
+        FOR EACH ROW
+    EXECUTE FUNCTION record_row_history(%L);', tablename, key_column);
+end;
+$$ language plpgsql;
+
+SELECT trigger_row_history('usr', 'user_id');
@@ -0,0 +1,45 @@
+-- See src/models/mod.rs
+
+create table role
+(
+    role_id       uuid primary key       default uuid_generate_v1mc(),
+    name          text unique not null,
+    description   text,
+    created_at    timestamptz            not null default now(),
+    updated_at    timestamptz
+);
+
+SELECT trigger_updated_at('role');
+
+create table user_group
+(
+    group_id      uuid primary key       default uuid_generate_v1mc(),
+    name          text unique not null,
+    description   text,
+    created_at    timestamptz            not null default now(),
+    updated_at    timestamptz
+);
+
+SELECT trigger_updated_at('user_group');
+
+-- Roles assigned to users
+create table user_role
+(
+    user_id       uuid        not null references usr on delete cascade,
+    role_id       uuid        not null references role on delete cascade,
+    created_at    timestamptz not null default now(),
+    primary key (user_id, role_id)
+);
+
+create index user_role_role_idx on user_role (role_id);
+
+-- Members of groups
+create table group_member
+(
+    group_id      uuid        not null references user_group on delete cascade,
+    user_id       uuid        not null references usr on delete cascade,
+    created_at    timestamptz not null default now(),
+    primary key (group_id, user_id)
+);
+
+create index group_member_user_idx on group_member (user_id);
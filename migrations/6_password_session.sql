@@ -0,0 +1,23 @@
+-- Credentials and login sessions of users.
+
+create table user_password
+(
+    user_id       uuid primary key       references usr on delete cascade,
+    -- Argon2, in PHC string format
+    password_hash text        not null,
+    updated_at    timestamptz not null default now()
+);
+
+create table user_session
+(
+    session_id    uuid primary key       default uuid_generate_v1mc(),
+    user_id       uuid        not null references usr on delete cascade,
+    -- SHA-256 of the token given to the client, which is not stored
+    token_hash    text unique not null,
+    created_at    timestamptz not null default now(),
+    expires_at    timestamptz not null,
+    user_agent    text,
+    ip            text
+);
+
+create index user_session_user_idx on user_session (user_id);
@@ -0,0 +1,16 @@
+-- Keys for the API clients.
+
+create table api_key
+(
+    key_id        uuid primary key       default uuid_generate_v1mc(),
+    name          text        not null,
+    -- SHA-256 of the key given to the client, which is not stored
+    key_hash      text unique not null,
+    -- start of the key, to help recognize it
+    prefix        text        not null,
+    -- e.g., {query,users:read}
+    scopes        text[]      not null,
+    created_at    timestamptz not null default now(),
+    last_used_at  timestamptz,
+    revoked_at    timestamptz
+);
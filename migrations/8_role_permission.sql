@@ -0,0 +1,10 @@
+-- Authorization: what the roles permit, and roles for the API keys.
+
+-- e.g., {query,users:read}
+alter table role add column permissions text[] not null default '{}';
+
+-- Postgres role set (`set local role`) for the generic queries of the principals
+-- with this role, so the database grants apply to them
+alter table role add column db_role text;
+
+alter table api_key add column roles text[] not null default '{}';
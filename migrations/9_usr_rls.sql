@@ -0,0 +1,16 @@
+-- Example of row-level security on `usr`, based on the identity of the caller of each request,
+-- which sqlxum sets as `request.user_id` (the principal) and `request.roles` (comma-separated).
+--
+-- RLS is enabled but not forced, so the policies apply to roles other than the table owner
+-- (usually the database user of the service), such as the `db_role` of the principals
+-- for the generic queries. To also apply them to the service's own queries:
+--   alter table usr force row level security;
+
+alter table usr enable row level security;
+
+-- A user can see (and update) only themself, unless having the `admin` role.
+create policy usr_self on usr
+    using (
+        user_id::text = current_setting('request.user_id', true)
+        or 'admin' = any (string_to_array(current_setting('request.roles', true), ','))
+    );
@@ -6,3 +6,20 @@
 pub fn unescape_query(query: &str) -> String {
     query.replace('`', "'")
 }
+
+/// A random token (256 bits, hex encoded), e.g., for a login session.
+pub fn new_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    to_hex(&bytes)
+}
+
+/// SHA-256 of the token (hex encoded), which is what gets stored,
+/// so the token itself cannot be recovered from the database.
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
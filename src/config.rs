@@ -1,23 +1,225 @@
 use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Config {
     pub database_url: String,
+    /// For the generic queries run as a `db_role`, connecting as an authenticator role.
+    pub query_database_url: Option<String>,
     pub port: u16,
     pub external_url: String,
+    pub history_file: PathBuf,
+    /// Size at which the history file is rotated.
+    pub history_max_bytes: u64,
+    pub cache: CacheConfig,
+    pub limits: LimitsConfig,
+    pub http: HttpConfig,
+    /// How long a login session remains valid.
+    pub session_ttl: Duration,
+    /// Whether the `/api` routes (other than health and login) require authentication.
+    pub require_auth: bool,
+    pub jwt: JwtConfig,
+}
+
+/// Settings for the cache of read-only generic query results.
+#[derive(Debug)]
+pub struct CacheConfig {
+    /// Maximum number of cached results. Zero disables the cache.
+    pub capacity: usize,
+    /// How long a cached result remains valid.
+    pub ttl: Duration,
+    /// Results larger than this (serialized) are not cached.
+    pub max_entry_bytes: usize,
+}
+
+/// Settings for the rate limiting and concurrency limits per client.
+/// All but the login throttle are disabled by default.
+#[derive(Debug)]
+pub struct LimitsConfig {
+    /// Sustained requests per second per client. Zero disables the rate limiting.
+    pub rate: f64,
+    /// Requests a client can make in a burst, above the sustained rate.
+    pub burst: u32,
+    /// Sustained requests per second per IP address, authenticated or not.
+    /// Zero disables the rate limiting per IP address.
+    pub ip_rate: f64,
+    /// Requests from an IP address in a burst, above the sustained rate.
+    pub ip_burst: u32,
+    /// Sustained login attempts per minute for the same email from the same IP address.
+    /// Zero disables the throttle.
+    pub login_rate: f64,
+    /// Login attempts for an email from an IP address in a burst, above the sustained rate.
+    pub login_burst: u32,
+    /// Whether the IP address of a client is taken from `X-Forwarded-For`,
+    /// as set by a reverse proxy in front of the service.
+    pub trust_forwarded_for: bool,
+    /// Generic queries executing at once, from all clients. Zero for no limit.
+    pub max_concurrent_queries: usize,
+    /// Generic queries executing at once from the same client. Zero for no limit.
+    pub max_concurrent_queries_per_client: usize,
+}
+
+/// Settings for the HTTP middleware of the service.
+#[derive(Debug)]
+pub struct HttpConfig {
+    /// Origins allowed for CORS, or `*` for any. Empty disables CORS.
+    pub cors_allowed_origins: Vec<String>,
+    pub cors_allowed_methods: Vec<String>,
+    pub cors_allowed_headers: Vec<String>,
+    /// Whether responses are compressed (gzip, brotli, or zstd), per `Accept-Encoding`.
+    pub compression: bool,
+    /// Maximum size of a request body.
+    pub body_limit: usize,
+    /// Time to handle a request. Zero for no limit.
+    pub request_timeout: Duration,
+}
+
+/// Settings for accepting JWTs as bearer tokens.
+/// Disabled unless a secret, public key, or JWKS file is given.
+#[derive(Debug)]
+pub struct JwtConfig {
+    /// `RS256` or `ES256`, for the public key.
+    /// For a JWKS, the default for keys without `alg` or a known `kty`.
+    pub algorithm: Option<String>,
+    /// Shared secret, for HS256.
+    pub secret: Option<String>,
+    /// PEM file with the public key, for RS256 or ES256.
+    pub public_key_file: Option<PathBuf>,
+    /// JWKS file, with the keys selected by the `kid` of each token.
+    pub jwks_file: Option<PathBuf>,
+    /// Required `aud` of the tokens.
+    pub audience: Option<String>,
+    /// Required `iss` of the tokens.
+    pub issuer: Option<String>,
+    /// Allowed clock skew when checking `exp` and `nbf`.
+    pub leeway: Duration,
+}
+
+/// Settings for the logs and traces, independent of the rest so they apply to all commands.
+#[derive(Debug)]
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// OTLP (gRPC) endpoint of a collector the spans are exported to, if any.
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of the exported spans.
+    pub service_name: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    /// One line per event, with the fields of the enclosing spans.
+    #[default]
+    Text,
+    /// Multiple lines per event, for reading during development.
+    Pretty,
+    /// One JSON object per event.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!("expecting text, pretty, or json")),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    pub fn get() -> anyhow::Result<Self> {
+        Ok(Self {
+            log_format: env_var_or("SQLXUM_LOG_FORMAT", LogFormat::default())?,
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            service_name: env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "sqlxum".to_string()),
+        })
+    }
+}
+
+impl JwtConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.secret.is_some() || self.public_key_file.is_some() || self.jwks_file.is_some()
+    }
 }
 
 impl Config {
     pub fn get() -> anyhow::Result<Self> {
         let database_url = req_env_var("DATABASE_URL")?;
+        let query_database_url = env::var("SQLXUM_QUERY_DATABASE_URL").ok();
         let port = env::var("SQLXUM_PORT").unwrap_or_else(|_| "8080".to_string());
         let port: u16 = port.parse()?;
         let external_url =
             env::var("EXTERNAL_URL").unwrap_or_else(|_| format!("http://localhost:{}", port));
+        let history_file = env::var("SQLXUM_HISTORY_FILE")
+            .unwrap_or_else(|_| "sqlxum_history.jsonl".to_string())
+            .into();
+        let history_max_bytes = env_var_or("SQLXUM_HISTORY_MAX_BYTES", 10 * 1024 * 1024)?;
+        let cache = CacheConfig {
+            capacity: env_var_or("SQLXUM_CACHE_CAPACITY", 0)?,
+            ttl: Duration::from_secs(env_var_or("SQLXUM_CACHE_TTL", 10)?),
+            max_entry_bytes: env_var_or("SQLXUM_CACHE_MAX_ENTRY_BYTES", 1024 * 1024)?,
+        };
+        let limits = LimitsConfig {
+            rate: env_var_or("SQLXUM_RATE_LIMIT", 0.0)?,
+            burst: env_var_or("SQLXUM_RATE_LIMIT_BURST", 20)?,
+            ip_rate: env_var_or("SQLXUM_IP_RATE_LIMIT", 0.0)?,
+            ip_burst: env_var_or("SQLXUM_IP_RATE_LIMIT_BURST", 20)?,
+            login_rate: env_var_or("SQLXUM_LOGIN_RATE_LIMIT", 5.0)?,
+            login_burst: env_var_or("SQLXUM_LOGIN_RATE_LIMIT_BURST", 5)?,
+            trust_forwarded_for: env_var_or("SQLXUM_TRUST_FORWARDED_FOR", false)?,
+            max_concurrent_queries: env_var_or("SQLXUM_MAX_CONCURRENT_QUERIES", 0)?,
+            max_concurrent_queries_per_client: env_var_or(
+                "SQLXUM_MAX_CONCURRENT_QUERIES_PER_CLIENT",
+                0,
+            )?,
+        };
+        let http = HttpConfig {
+            cors_allowed_origins: env_list_or("SQLXUM_CORS_ALLOWED_ORIGINS", ""),
+            cors_allowed_methods: env_list_or(
+                "SQLXUM_CORS_ALLOWED_METHODS",
+                "GET,POST,PUT,PATCH,DELETE",
+            ),
+            cors_allowed_headers: env_list_or(
+                "SQLXUM_CORS_ALLOWED_HEADERS",
+                "authorization,cache-control,content-type,if-match,if-none-match,x-api-key,x-request-id",
+            ),
+            compression: env_var_or("SQLXUM_COMPRESSION", true)?,
+            body_limit: env_var_or("SQLXUM_BODY_LIMIT", 10 * 1024 * 1024)?,
+            request_timeout: Duration::from_secs(env_var_or("SQLXUM_REQUEST_TIMEOUT", 60)?),
+        };
+        let session_ttl = Duration::from_secs(env_var_or("SQLXUM_SESSION_TTL", 12 * 60 * 60)?);
+        let require_auth = env_var_or("SQLXUM_REQUIRE_AUTH", false)?;
+        let jwt = JwtConfig {
+            algorithm: env::var("SQLXUM_JWT_ALGORITHM").ok(),
+            secret: env::var("SQLXUM_JWT_SECRET").ok(),
+            public_key_file: env::var("SQLXUM_JWT_PUBLIC_KEY_FILE")
+                .ok()
+                .map(PathBuf::from),
+            jwks_file: env::var("SQLXUM_JWT_JWKS_FILE").ok().map(PathBuf::from),
+            audience: env::var("SQLXUM_JWT_AUDIENCE").ok(),
+            issuer: env::var("SQLXUM_JWT_ISSUER").ok(),
+            leeway: Duration::from_secs(env_var_or("SQLXUM_JWT_LEEWAY", 60)?),
+        };
         Ok(Self {
             database_url,
+            query_database_url,
             port,
             external_url,
+            history_file,
+            history_max_bytes,
+            cache,
+            limits,
+            http,
+            session_ttl,
+            require_auth,
+            jwt,
         })
     }
 }
@@ -25,3 +227,22 @@ impl Config {
 fn req_env_var(name: &str) -> anyhow::Result<String> {
     env::var(name).map_err(|_| anyhow::anyhow!("envvar '{}' not set", name))
 }
+
+/// Comma-separated values, ignoring empty ones.
+fn env_list_or(name: &str, default: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn env_var_or<T: FromStr>(name: &str, default: T) -> anyhow::Result<T> {
+    match env::var(name) {
+        Ok(val) => val
+            .parse()
+            .map_err(|_| anyhow::anyhow!("envvar '{}': invalid value '{}'", name, val)),
+        Err(_) => Ok(default),
+    }
+}
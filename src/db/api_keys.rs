@@ -0,0 +1,105 @@
+//! Keys for the API clients, stored with only a hash of the key itself.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::common::{hash_token, new_token};
+use crate::models::Permission;
+
+/// Keys are given to the clients with this prefix, so they are easy to recognize.
+const KEY_PREFIX: &str = "sqlxum_";
+
+#[derive(sqlx::FromRow, Serialize, Debug)]
+pub struct ApiKey {
+    pub key_id: uuid::Uuid,
+    pub name: String,
+    /// Start of the key, to help recognize it.
+    pub prefix: String,
+    /// Permissions granted directly to the key.
+    pub scopes: Vec<String>,
+    /// Names of the roles of the key, for further permissions.
+    pub roles: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Creates a key, returning it along with the key itself, which is not stored.
+#[tracing::instrument(skip_all)]
+pub async fn create_api_key(
+    pool: &sqlx::PgPool,
+    name: &str,
+    scopes: &[Permission],
+    roles: &[String],
+) -> anyhow::Result<(ApiKey, String)> {
+    let key = format!("{KEY_PREFIX}{}", new_token());
+    let prefix = key[..KEY_PREFIX.len() + 8].to_string();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_string()).collect();
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+            insert into api_key (name, key_hash, prefix, scopes, roles)
+            values ($1, $2, $3, $4, $5)
+            returning key_id, name, prefix, scopes, roles, created_at, last_used_at, revoked_at
+        "#,
+        name,
+        hash_token(&key),
+        prefix,
+        &scopes,
+        roles,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((api_key, key))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_api_keys(pool: &sqlx::PgPool) -> anyhow::Result<Vec<ApiKey>> {
+    let keys = sqlx::query_as!(
+        ApiKey,
+        r#"
+            select key_id, name, prefix, scopes, roles, created_at, last_used_at, revoked_at
+            from api_key
+            order by created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(keys)
+}
+
+/// Returns `false` if there is no such key not already revoked.
+#[tracing::instrument(skip_all)]
+pub async fn revoke_api_key(pool: &sqlx::PgPool, key_id: &uuid::Uuid) -> anyhow::Result<bool> {
+    let res = sqlx::query!(
+        "update api_key set revoked_at = now() where key_id = $1 and revoked_at is null",
+        key_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// The (not revoked) key with the given value, if any, recording it as used.
+#[tracing::instrument(skip_all)]
+pub async fn authenticate(pool: &sqlx::PgPool, key: &str) -> anyhow::Result<Option<ApiKey>> {
+    if !key.starts_with(KEY_PREFIX) {
+        return Ok(None);
+    }
+    let api_key = sqlx::query_as!(
+        ApiKey,
+        r#"
+            update api_key
+             set last_used_at = now()
+            where key_hash = $1
+              and revoked_at is null
+            returning key_id, name, prefix, scopes, roles, created_at, last_used_at, revoked_at
+        "#,
+        hash_token(key),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(api_key)
+}
@@ -0,0 +1,238 @@
+//! Bulk import and export of users as a JSON array, NDJSON, or CSV.
+
+use futures::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::Acquire;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::pool::acquire;
+use crate::db::{bad_input, identity, run_traced, traced};
+use crate::models::User;
+use crate::server::database::UserPostReq;
+use crate::server::validation::{field_errors, Normalize};
+
+/// Format of the users in a bulk import or export.
+#[derive(clap::ValueEnum, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// A JSON array of objects.
+    #[default]
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    /// With a header line, e.g., `email,name`.
+    Csv,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv",
+        }
+    }
+
+    /// The format for the given content type, if recognized.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        match mime {
+            "application/json" => Some(Format::Json),
+            "application/x-ndjson" | "application/jsonl" => Some(Format::Ndjson),
+            "text/csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// The format for the given file extension, if recognized.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "json" => Some(Format::Json),
+            "ndjson" | "jsonl" => Some(Format::Ndjson),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// A user that could not be imported.
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RowError {
+    /// Number of the user in the input, starting at 1 (not counting any CSV header).
+    pub row: usize,
+    pub error: String,
+}
+
+/// Outcome of a bulk import.
+#[derive(Serialize, ToSchema, Debug, Default)]
+pub struct ImportReport {
+    /// Number of users inserted.
+    pub inserted: u64,
+    /// Users that could not be imported.
+    /// Unless continuing on error, nothing is inserted if there are any.
+    pub errors: Vec<RowError>,
+}
+
+/// Parses the users in the given data.
+/// Each user is normalized and validated as in `POST /api/users`,
+/// with any failure reported for the corresponding row.
+/// An error is only returned if the data as a whole cannot be parsed, e.g., an invalid JSON array.
+pub fn parse_users(format: Format, data: &str) -> anyhow::Result<Vec<Result<UserPostReq, String>>> {
+    let rows: Vec<Result<UserPostReq, String>> = match format {
+        Format::Json => {
+            let values: Vec<Value> =
+                serde_json::from_str(data).map_err(|e| bad_input(e.to_string()))?;
+            values
+                .into_iter()
+                .map(|v| serde_json::from_value(v).map_err(|e| e.to_string()))
+                .collect()
+        }
+        Format::Ndjson => data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+            .collect(),
+        Format::Csv => csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(data.as_bytes())
+            .deserialize()
+            .map(|r| r.map_err(|e| e.to_string()))
+            .collect(),
+    };
+    Ok(rows.into_iter().map(|r| r.and_then(validate)).collect())
+}
+
+fn validate(mut req: UserPostReq) -> Result<UserPostReq, String> {
+    req.normalize();
+    req.validate().map_err(|e| {
+        field_errors(&e)
+            .into_iter()
+            .map(|(field, messages)| format!("{field}: {}", messages.join(", ")))
+            .collect::<Vec<_>>()
+            .join("; ")
+    })?;
+    Ok(req)
+}
+
+/// Imports the users in a single transaction.
+///
+/// By default, nothing is inserted if any row is invalid, and the users are loaded with `COPY`,
+/// so a database error (e.g., a duplicate email) aborts the whole import.
+/// With `continue_on_error`, each user is inserted under its own savepoint,
+/// so the failing ones are reported and the rest are still inserted.
+#[tracing::instrument(skip_all)]
+pub async fn import_users(
+    pool: &sqlx::PgPool,
+    rows: Vec<Result<UserPostReq, String>>,
+    continue_on_error: bool,
+) -> anyhow::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut users = vec![];
+    for (i, row) in rows.into_iter().enumerate() {
+        match row {
+            Ok(user) => users.push((i + 1, user)),
+            Err(error) => report.errors.push(RowError { row: i + 1, error }),
+        }
+    }
+    if !report.errors.is_empty() && !continue_on_error {
+        return Ok(report);
+    }
+
+    let mut tx = identity::begin(pool).await?;
+    if continue_on_error {
+        for (row, user) in users {
+            let mut savepoint = tx.begin().await?;
+            let res = traced!(
+                sqlx::query!(
+                    "insert into usr (email, name) values ($1, $2)",
+                    user.email,
+                    user.name,
+                ),
+                execute(&mut *savepoint)
+            );
+            match res {
+                Ok(_) => {
+                    savepoint.commit().await?;
+                    report.inserted += 1;
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    report.errors.push(RowError {
+                        row,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        report.errors.sort_by_key(|e| e.row);
+    } else if !users.is_empty() {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for (_, user) in &users {
+            writer.write_record([&user.email, &user.name])?;
+        }
+        let data = writer.into_inner()?;
+        let statement = "copy usr (email, name) from stdin with (format csv)";
+        let copy_in = async {
+            let mut copy = tx.copy_in_raw(statement).await?;
+            copy.send(data).await?;
+            copy.finish().await
+        };
+        report.inserted = run_traced(statement, copy_in, |rows| *rows).await?;
+    }
+    tx.commit().await?;
+    tracing::info!(
+        "import_users: {} inserted, {} errors",
+        report.inserted,
+        report.errors.len()
+    );
+    Ok(report)
+}
+
+/// All the users not deleted, oldest first, in the given format.
+/// CSV is produced directly by the database with `COPY`.
+#[tracing::instrument(skip_all)]
+pub async fn export_users(pool: &sqlx::PgPool, format: Format) -> anyhow::Result<Vec<u8>> {
+    if format == Format::Csv {
+        let mut conn = acquire(pool).await?;
+        let statement = r#"
+            copy (select user_id, email, name, created_at, updated_at
+                  from usr
+                  where deleted_at is null
+                  order by created_at, user_id)
+            to stdout with (format csv, header)
+        "#;
+        let copy_out = async {
+            let mut stream = conn.copy_out_raw(statement).await?;
+            let mut data = vec![];
+            while let Some(chunk) = stream.try_next().await? {
+                data.extend_from_slice(&chunk);
+            }
+            Ok(data)
+        };
+        // The rows are the lines after the header, unless a name spans several lines.
+        let lines = |data: &Vec<u8>| data.iter().filter(|&&b| b == b'\n').count() as u64;
+        let data = run_traced(statement, copy_out, |data| lines(data).saturating_sub(1)).await?;
+        return Ok(data);
+    }
+
+    let users = traced!(
+        sqlx::query_as!(
+            User,
+            "select * from usr where deleted_at is null order by created_at, user_id"
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(match format {
+        Format::Ndjson => {
+            let mut data = vec![];
+            for user in &users {
+                serde_json::to_writer(&mut data, user)?;
+                data.push(b'\n');
+            }
+            data
+        }
+        _ => serde_json::to_vec_pretty(&users)?,
+    })
+}
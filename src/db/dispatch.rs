@@ -1,25 +1,153 @@
 use sqlx::postgres::PgPoolOptions;
+use std::io::{Read, Write};
+use std::time::Instant;
 
 use crate::config::Config;
+use crate::db::bulk::{self, Format};
 use crate::db::generic::do_query;
-use crate::db::DbOpts;
+use crate::db::history::{History, QueryRecord};
+use crate::db::{api_keys, schema, users};
+use crate::db::{ApiKeyCmd, DbCmd, DbOpts};
 
 pub(crate) async fn dispatch(opts: &DbOpts) -> anyhow::Result<()> {
     let config = Config::get()?;
+    let history = History::new(config.history_file.clone(), config.history_max_bytes);
+
+    if let Some(DbCmd::History(filter)) = &opts.cmd {
+        let records = history.read(filter).await?;
+        println!("{}", serde_json::to_string_pretty(&records)?);
+        return Ok(());
+    }
+
     let pool = create_pool(&config).await?;
 
+    match &opts.cmd {
+        Some(DbCmd::Tables { schema }) => {
+            let tables = schema::get_tables(&pool, schema.as_deref()).await?;
+            println!("{}", serde_json::to_string_pretty(&tables)?);
+        }
+        Some(DbCmd::Describe { table }) => {
+            let (schema, name) = schema::split_table_name(table);
+            match schema::describe_table(&pool, schema, name).await? {
+                Some(desc) => println!("{}", serde_json::to_string_pretty(&desc)?),
+                None => anyhow::bail!("table '{}' not found", table),
+            }
+        }
+        Some(DbCmd::ImportUsers {
+            file,
+            format,
+            continue_on_error,
+        }) => {
+            let format = format
+                .or_else(|| {
+                    let ext = file.extension()?.to_str()?;
+                    Format::from_extension(ext)
+                })
+                .unwrap_or_default();
+            let data = if file.as_os_str() == "-" {
+                let mut data = String::new();
+                std::io::stdin().read_to_string(&mut data)?;
+                data
+            } else {
+                std::fs::read_to_string(file)?
+            };
+            let rows = bulk::parse_users(format, &data)?;
+            let report = bulk::import_users(&pool, rows, *continue_on_error).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if report.inserted == 0 && !report.errors.is_empty() {
+                anyhow::bail!("no users imported");
+            }
+        }
+        Some(DbCmd::RestoreUser { user_id }) => match users::restore_user(&pool, user_id).await? {
+            Some(user) => println!("{}", serde_json::to_string_pretty(&user)?),
+            None => anyhow::bail!("deleted user '{}' not found", user_id),
+        },
+        Some(DbCmd::PurgeUsers {
+            user_id,
+            deleted_before,
+        }) => {
+            let user_ids = match user_id {
+                Some(user_id) => match users::purge_user(&pool, user_id).await? {
+                    Some(user_id) => vec![user_id],
+                    None => anyhow::bail!("deleted user '{}' not found", user_id),
+                },
+                None => users::purge_users(&pool, *deleted_before).await?,
+            };
+            println!("{}", serde_json::to_string_pretty(&user_ids)?);
+        }
+        Some(DbCmd::ApiKey { cmd }) => match cmd {
+            ApiKeyCmd::Create {
+                name,
+                scopes,
+                roles,
+            } => {
+                let (api_key, key) = api_keys::create_api_key(&pool, name, scopes, roles).await?;
+                println!("{}", serde_json::to_string_pretty(&api_key)?);
+                println!("Key (only shown now): {key}");
+            }
+            ApiKeyCmd::List => {
+                let keys = api_keys::get_api_keys(&pool).await?;
+                println!("{}", serde_json::to_string_pretty(&keys)?);
+            }
+            ApiKeyCmd::Revoke { key_id } => {
+                if !api_keys::revoke_api_key(&pool, key_id).await? {
+                    anyhow::bail!("key '{}' not found or already revoked", key_id);
+                }
+            }
+        },
+        Some(DbCmd::ExportUsers { format, output }) => {
+            let data = bulk::export_users(&pool, *format).await?;
+            match output {
+                Some(path) => std::fs::write(path, data)?,
+                None => std::io::stdout().write_all(&data)?,
+            }
+        }
+        _ => (),
+    }
+
     if let Some(query) = &opts.query {
-        let res = do_query(&pool, query).await?;
-        println!("{}", serde_json::to_string_pretty(&res)?);
+        let start = Instant::now();
+        let res = do_query(&pool, query, &[], opts.read_only, None).await;
+        let rec = QueryRecord::new(query, &[], Some("cli".to_string()), start.elapsed(), &res);
+        history.record(&rec).await;
+        println!("{}", serde_json::to_string_pretty(&res?)?);
     }
     Ok(())
 }
 
 pub async fn create_pool(config: &Config) -> sqlx::Result<sqlx::PgPool> {
-    log::info!("Connecting to database...");
+    tracing::info!("Connecting to database...");
+    connect(&config.database_url).await
+}
+
+/// The pool for the generic queries run as a `db_role`, if configured.
+/// Its user must be an authenticator: without privileges of its own, so that resetting
+/// the role (which is always allowed) does not give any.
+pub async fn create_query_pool(config: &Config) -> anyhow::Result<Option<sqlx::PgPool>> {
+    let Some(url) = &config.query_database_url else {
+        return Ok(None);
+    };
+    tracing::info!("Connecting to database for the queries as a db_role...");
+    let pool = connect(url).await?;
+    let (name, inherit, privileged): (String, bool, bool) = sqlx::query_as(
+        "select rolname::text, rolinherit, rolsuper or rolbypassrls or rolcreaterole
+         from pg_roles where rolname = current_user",
+    )
+    .fetch_one(&pool)
+    .await?;
+    if inherit || privileged {
+        anyhow::bail!(
+            "SQLXUM_QUERY_DATABASE_URL: role '{name}' must be NOINHERIT, and not a superuser, \
+             nor have BYPASSRLS or CREATEROLE"
+        );
+    }
+    Ok(Some(pool))
+}
+
+async fn connect(url: &str) -> sqlx::Result<sqlx::PgPool> {
     PgPoolOptions::new()
         .max_connections(5)
         .acquire_timeout(std::time::Duration::from_secs(9))
-        .connect(&config.database_url)
+        .connect(url)
         .await
 }
@@ -0,0 +1,417 @@
+//! Structured filters, e.g., `email=eq.foo@example.net` or `name=ilike.*foo*`,
+//! compiled to SQL conditions with bound parameters.
+//!
+//! Conditions can be grouped with `or=(<cond>,<cond>,...)` and `and=(...)`,
+//! where each `<cond>` is `<column>.[not.]<op>.<value>` or a nested
+//! `or(...)`/`and(...)` group, e.g., `or=(name.eq.Foo,and(email.like.*@x.net,created_at.gt.2024-02-18))`.
+
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::db::bad_input;
+use crate::db::schema::quote_ident;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Neq,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Like,
+    Ilike,
+    In,
+    Is,
+}
+
+impl Op {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "eq" => Op::Eq,
+            "neq" => Op::Neq,
+            "gt" => Op::Gt,
+            "gte" => Op::Gte,
+            "lt" => Op::Lt,
+            "lte" => Op::Lte,
+            "like" => Op::Like,
+            "ilike" => Op::Ilike,
+            "in" => Op::In,
+            "is" => Op::Is,
+            _ => return None,
+        })
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Neq => "<>",
+            Op::Gt => ">",
+            Op::Gte => ">=",
+            Op::Lt => "<",
+            Op::Lte => "<=",
+            Op::Like => "like",
+            Op::Ilike => "ilike",
+            Op::In => "in",
+            Op::Is => "is",
+        }
+    }
+}
+
+/// A condition on a column, as given by `<column>=[not.]<op>.<value>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub column: String,
+    pub negated: bool,
+    pub op: Op,
+    pub value: String,
+}
+
+impl Condition {
+    /// Parses the `[not.]<op>.<value>` part of a condition on the given column.
+    pub fn parse(column: &str, spec: &str) -> anyhow::Result<Self> {
+        let (negated, spec) = match spec.strip_prefix("not.") {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (op, value) = spec
+            .split_once('.')
+            .ok_or_else(|| bad_input(format!("expecting <op>.<value> for '{column}'")))?;
+        let op = Op::parse(op).ok_or_else(|| bad_input(format!("unknown operator '{op}'")))?;
+        if op == Op::Is && !matches!(value, "null" | "true" | "false") {
+            return Err(bad_input("'is' only accepts null, true, or false"));
+        }
+        Ok(Condition {
+            column: column.to_string(),
+            negated,
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    /// Pushes the SQL for this condition, with the value bound as a parameter
+    /// and cast to the given column type.
+    /// The `data_type` must come from the catalog, not from user input.
+    pub fn push_sql(&self, qb: &mut QueryBuilder<'_, Postgres>, data_type: &str) {
+        let column = quote_ident(&self.column);
+        if self.negated {
+            qb.push("not ");
+        }
+        qb.push("(");
+        match self.op {
+            Op::Is => {
+                // value already validated as a keyword
+                qb.push(format!("{column} is {}", self.value));
+            }
+            Op::Like | Op::Ilike => {
+                qb.push(format!("{column}::text {} ", self.op.sql()));
+                qb.push_bind(self.value.replace('*', "%"));
+            }
+            Op::In => {
+                qb.push(format!("{column} = any("));
+                qb.push_bind(parse_list(&self.value));
+                qb.push(format!("::text[]::{data_type}[])"));
+            }
+            _ => {
+                qb.push(format!("{column} {} ", self.op.sql()));
+                qb.push_bind(self.value.clone());
+                qb.push(format!("::text::{data_type}"));
+            }
+        }
+        qb.push(")");
+    }
+}
+
+/// Parses a list like `(a,b,c)`.
+fn parse_list(value: &str) -> Vec<String> {
+    let value = value.trim_start_matches('(').trim_end_matches(')');
+    value.split(',').map(|v| v.trim().to_string()).collect()
+}
+
+/// A tree of conditions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Cond(Condition),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    /// Parses a query parameter: `or=(...)`, `and=(...)`, or `<column>=[not.]<op>.<value>`.
+    pub fn parse_param(key: &str, value: &str) -> anyhow::Result<Self> {
+        match key {
+            "or" | "and" => Self::parse_group(key, value),
+            _ => Ok(Filter::Cond(Condition::parse(key, value)?)),
+        }
+    }
+
+    /// Parses `(<item>,<item>,...)` as an `and` or `or` group.
+    fn parse_group(kind: &str, value: &str) -> anyhow::Result<Self> {
+        let inner = value
+            .strip_prefix('(')
+            .and_then(|v| v.strip_suffix(')'))
+            .ok_or_else(|| bad_input(format!("expecting parenthesized list for '{kind}'")))?;
+        let items = split_top_level(inner)
+            .into_iter()
+            .map(Self::parse_item)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if items.is_empty() {
+            return Err(bad_input(format!("empty list for '{kind}'")));
+        }
+        Ok(if kind == "or" {
+            Filter::Or(items)
+        } else {
+            Filter::And(items)
+        })
+    }
+
+    /// Parses a group item: a nested `or(...)`/`and(...)`, or `<column>.[not.]<op>.<value>`.
+    fn parse_item(item: &str) -> anyhow::Result<Self> {
+        for kind in ["or", "and"] {
+            if let Some(rest) = item.strip_prefix(kind) {
+                if rest.starts_with('(') {
+                    return Self::parse_group(kind, rest);
+                }
+            }
+        }
+        let (column, spec) = item
+            .split_once('.')
+            .ok_or_else(|| bad_input(format!("expecting <column>.<op>.<value>, got '{item}'")))?;
+        Ok(Filter::Cond(Condition::parse(column, spec)?))
+    }
+
+    /// Pushes the SQL for this filter.
+    /// `data_type` gives the type of a column, or `None` if the column is unknown.
+    pub fn push_sql<'a>(
+        &self,
+        qb: &mut QueryBuilder<'_, Postgres>,
+        data_type: &impl Fn(&str) -> Option<&'a str>,
+    ) -> anyhow::Result<()> {
+        let (items, sep) = match self {
+            Filter::Cond(cond) => {
+                let data_type = data_type(&cond.column)
+                    .ok_or_else(|| bad_input(format!("unknown column '{}'", cond.column)))?;
+                cond.push_sql(qb, data_type);
+                return Ok(());
+            }
+            Filter::And(items) => (items, " and "),
+            Filter::Or(items) => (items, " or "),
+        };
+        qb.push("(");
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                qb.push(sep);
+            }
+            item.push_sql(qb, data_type)?;
+        }
+        qb.push(")");
+        Ok(())
+    }
+}
+
+/// Splits on commas not enclosed in parentheses.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut items = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    items.push(s[start..].trim());
+    items.retain(|item| !item.is_empty());
+    items
+}
+
+/// Pushes `where` followed by the conjunction of the filters, if any.
+/// `data_type` gives the type of a column, or `None` if the column is unknown.
+pub fn push_where<'a>(
+    qb: &mut QueryBuilder<'_, Postgres>,
+    filters: &[Filter],
+    data_type: impl Fn(&str) -> Option<&'a str>,
+) -> anyhow::Result<()> {
+    for (i, filter) in filters.iter().enumerate() {
+        qb.push(if i == 0 { " where " } else { " and " });
+        filter.push_sql(qb, &data_type)?;
+    }
+    Ok(())
+}
+
+/// Parses a comma-separated list of columns to order by,
+/// each optionally followed by `.asc` or `.desc`, e.g., `name.desc,created_at`.
+/// Returns the columns with a flag indicating descending order.
+pub fn parse_order(value: &str) -> Vec<(String, bool)> {
+    value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.rsplit_once('.') {
+            Some((col, "asc")) => (col.to_string(), false),
+            Some((col, "desc")) => (col.to_string(), true),
+            _ => (item.to_string(), false),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::BadInput;
+
+    fn data_type(column: &str) -> Option<&'static str> {
+        match column {
+            "name" | "email" => Some("text"),
+            "created_at" => Some("timestamptz"),
+            "weird\"col" => Some("int4"),
+            _ => None,
+        }
+    }
+
+    fn to_sql(filter: &Filter) -> anyhow::Result<String> {
+        let mut qb = QueryBuilder::new("");
+        filter.push_sql(&mut qb, &data_type)?;
+        Ok(qb.sql().to_string())
+    }
+
+    fn cond(column: &str, negated: bool, op: Op, value: &str) -> Filter {
+        Filter::Cond(Condition {
+            column: column.to_string(),
+            negated,
+            op,
+            value: value.to_string(),
+        })
+    }
+
+    #[test]
+    fn parses_conditions() {
+        assert_eq!(
+            Filter::parse_param("email", "eq.foo@example.net").unwrap(),
+            cond("email", false, Op::Eq, "foo@example.net")
+        );
+        // only the first dot separates the operator from the value
+        assert_eq!(
+            Filter::parse_param("name", "not.like.*a.b*").unwrap(),
+            cond("name", true, Op::Like, "*a.b*")
+        );
+        assert_eq!(
+            Filter::parse_param("name", "is.null").unwrap(),
+            cond("name", false, Op::Is, "null")
+        );
+    }
+
+    #[test]
+    fn rejects_disallowed_operators() {
+        for (key, value) in [
+            ("name", "regex.x"),
+            ("name", "not.not.eq.x"),
+            ("name", "eq"),
+            ("name", "is.x; drop table usr"),
+            ("or", "(name.between.a)"),
+        ] {
+            let e = Filter::parse_param(key, value).unwrap_err();
+            assert!(e.is::<BadInput>(), "{key}={value}: {e}");
+        }
+    }
+
+    #[test]
+    fn parses_nested_groups() {
+        let filter = Filter::parse_param(
+            "or",
+            "(name.eq.Foo,and(email.like.*@x.net,created_at.gt.2024-02-18))",
+        )
+        .unwrap();
+        assert_eq!(
+            filter,
+            Filter::Or(vec![
+                cond("name", false, Op::Eq, "Foo"),
+                Filter::And(vec![
+                    cond("email", false, Op::Like, "*@x.net"),
+                    cond("created_at", false, Op::Gt, "2024-02-18"),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_groups() {
+        for value in ["name.eq.Foo", "()", "( , )", "(name)", "(or(name.eq.a)"] {
+            let e = Filter::parse_param("and", value).unwrap_err();
+            assert!(e.is::<BadInput>(), "{value}: {e}");
+        }
+    }
+
+    #[test]
+    fn splits_on_top_level_commas() {
+        assert_eq!(
+            split_top_level("a.eq.1, or(b.eq.2,c.eq.3) ,d.in.(4,5)"),
+            vec!["a.eq.1", "or(b.eq.2,c.eq.3)", "d.in.(4,5)"]
+        );
+        assert_eq!(split_top_level(",a.eq.1,,"), vec!["a.eq.1"]);
+        assert!(split_top_level("").is_empty());
+    }
+
+    #[test]
+    fn pushes_values_as_parameters_cast_to_the_column_type() {
+        let filter = Filter::parse_param("created_at", "gte.2024-02-18").unwrap();
+        assert_eq!(
+            to_sql(&filter).unwrap(),
+            r#"("created_at" >= $1::text::timestamptz)"#
+        );
+        let filter = Filter::parse_param("name", "eq.x' or '1'='1").unwrap();
+        assert_eq!(to_sql(&filter).unwrap(), r#"("name" = $1::text::text)"#);
+        let filter = Filter::parse_param("email", "not.ilike.*@X.NET").unwrap();
+        assert_eq!(to_sql(&filter).unwrap(), r#"not ("email"::text ilike $1)"#);
+        let filter = Filter::parse_param("name", "in.(a,b)").unwrap();
+        assert_eq!(
+            to_sql(&filter).unwrap(),
+            r#"("name" = any($1::text[]::text[]))"#
+        );
+        let filter = Filter::parse_param("name", "is.null").unwrap();
+        assert_eq!(to_sql(&filter).unwrap(), r#"("name" is null)"#);
+    }
+
+    #[test]
+    fn quotes_column_names() {
+        let filter = Filter::parse_param("weird\"col", "eq.1").unwrap();
+        assert_eq!(
+            to_sql(&filter).unwrap(),
+            r#"("weird""col" = $1::text::int4)"#
+        );
+    }
+
+    #[test]
+    fn pushes_nested_groups() {
+        let filter = Filter::parse_param(
+            "or",
+            "(name.eq.Foo,and(email.like.*@x.net,created_at.gt.2024-02-18))",
+        )
+        .unwrap();
+        assert_eq!(
+            to_sql(&filter).unwrap(),
+            r#"(("name" = $1::text::text) or (("email"::text like $2) and ("created_at" > $3::text::timestamptz)))"#
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        for (key, value) in [
+            ("password", "eq.x"),
+            ("or", "(name.eq.Foo,and(email.eq.x,secret.eq.y))"),
+        ] {
+            let filter = Filter::parse_param(key, value).unwrap();
+            let e = to_sql(&filter).unwrap_err();
+            assert!(e.is::<BadInput>(), "{key}={value}: {e}");
+        }
+    }
+
+    #[test]
+    fn parses_list_values() {
+        assert_eq!(parse_list("(a, b ,c)"), vec!["a", "b", "c"]);
+    }
+}
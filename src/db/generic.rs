@@ -2,34 +2,76 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::StreamExt;
 use serde_json::{json, Value};
 use sqlx::postgres::{PgColumn, PgRow};
-use sqlx::{Column, Row, TypeInfo};
+use sqlx::{Column, Postgres, Row, Transaction, TypeInfo};
 use std::fmt::Display;
 use std::time::Instant;
 
 use crate::common::unescape_query;
+use crate::db::pool::acquire;
+use crate::db::{bad_input, identity, record_sql_done};
 
-/// Performs a query, returning a Json array with the result
-pub async fn do_query(pool: &sqlx::PgPool, query: &str) -> anyhow::Result<Value> {
+/// Performs a query, returning a Json array with the result.
+/// Any given `params` are bound to the `$1`, `$2`, ... placeholders in the query.
+/// With `read_only`, the query is run in a read-only transaction.
+/// With `db_role`, the query is run as that Postgres role (`set local role`);
+/// the pool must then connect as a user without privileges of its own,
+/// as the query could reset the role to it.
+/// Within a request, the query runs in a transaction with the identity of the caller
+/// (see `identity::begin`), for the row-level security policies, and cannot change
+/// the settings (see [`check_settings`]).
+/// Traced in a span with the statement, and the rows and duration once done.
+#[tracing::instrument(
+    name = "sql",
+    skip_all,
+    fields(db.statement, read_only, db_role, rows, duration_ms)
+)]
+pub async fn do_query(
+    pool: &sqlx::PgPool,
+    query: &str,
+    params: &[Value],
+    read_only: bool,
+    db_role: Option<&str>,
+) -> anyhow::Result<Value> {
     let query = unescape_query(query);
-    log::info!("do_query: {}", query);
+    let span = tracing::Span::current();
+    span.record("db.statement", &query);
+    span.record("read_only", read_only);
+    span.record("db_role", db_role);
+    tracing::info!(
+        "do_query: {} params={:?} read_only={} db_role={:?}",
+        query,
+        params,
+        read_only,
+        db_role
+    );
 
-    let mut result: Vec<Value> = vec![];
-
-    let mut add_row = |row: &PgRow| {
-        let mut obj = json!({});
-        row.columns().iter().for_each(|col| {
-            let value = get_col_value(row, col);
-            obj[col.name()] = value;
-        });
-        result.push(obj);
-    };
+    if db_role.is_some() || identity::current().is_some() {
+        check_settings(&query)?;
+    }
 
     let start = Instant::now();
-    let mut stream = sqlx::query(&query).fetch(pool);
-    while let Some(res) = stream.next().await {
-        add_row(&(res?));
+    let mut q = sqlx::query(&query);
+    for param in params {
+        q = bind_param(q, param);
     }
-    let elapsed = format!("{:?}", start.elapsed());
+    let result = if read_only || db_role.is_some() || identity::current().is_some() {
+        let mut tx = begin_as(pool, read_only, db_role).await?;
+        let result = collect_rows(q, &mut *tx).await?;
+        if read_only {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+        result
+    } else {
+        let mut conn = acquire(pool).await?;
+        collect_rows(q, &mut *conn).await?
+    };
+    let duration = start.elapsed();
+    record_sql_done(&span, result.len() as u64, duration);
+    metrics::histogram!("sqlxum_query_duration_seconds").record(duration.as_secs_f64());
+    metrics::histogram!("sqlxum_query_rows").record(result.len() as f64);
+    let elapsed = format!("{:?}", duration);
 
     Ok(json!({
         "query": query,
@@ -38,6 +80,108 @@ pub async fn do_query(pool: &sqlx::PgPool, query: &str) -> anyhow::Result<Value>
     }))
 }
 
+/// Begins a transaction with the identity of the caller (see `identity::begin`),
+/// read-only if so requested, and as the `db_role` if any.
+pub async fn begin_as(
+    pool: &sqlx::PgPool,
+    read_only: bool,
+    db_role: Option<&str>,
+) -> sqlx::Result<Transaction<'static, Postgres>> {
+    let mut tx = identity::begin(pool).await?;
+    if read_only {
+        sqlx::query("set transaction read only")
+            .execute(&mut *tx)
+            .await?;
+    }
+    if let Some(db_role) = db_role {
+        let db_role = db_role.replace('"', "\"\"");
+        sqlx::query(&format!("set local role \"{db_role}\""))
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(tx)
+}
+
+/// Functions a query cannot call, as they change settings (`set_config`), or run other
+/// SQL given as text (`query_to_xml` and its variants), which could call them.
+const DISALLOWED_FUNCTIONS: [&str; 2] = ["set_config", "query_to_xml"];
+
+/// Statements a query cannot be, as they change settings, or run other SQL (`do`).
+const DISALLOWED_STATEMENTS: [&str; 3] = ["set", "reset", "do"];
+
+/// Rejects a query that could change the settings of the transaction: the identity
+/// (`request.user_id`, `request.roles`) the row-level security policies rely on, or the role.
+/// This is a safeguard, not a sandbox: SQL run by a function the caller can create or call
+/// (other than those checked here) could still change them.
+pub fn check_settings(query: &str) -> anyhow::Result<()> {
+    let query = query.to_lowercase();
+    if let Some(f) = DISALLOWED_FUNCTIONS.iter().find(|f| query.contains(*f)) {
+        return Err(bad_input(format!("{f} is not allowed in a query")));
+    }
+    let keyword = first_keyword(&query);
+    if DISALLOWED_STATEMENTS.contains(&keyword) {
+        return Err(bad_input(format!("'{keyword}' is not allowed as a query")));
+    }
+    Ok(())
+}
+
+/// The first word of the query, after any comments and opening parentheses.
+fn first_keyword(query: &str) -> &str {
+    let mut rest = query;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '(');
+        if let Some(comment) = rest.strip_prefix("--") {
+            rest = comment.split_once('\n').map_or("", |(_, r)| r);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.split_once("*/").map_or("", |(_, r)| r);
+        } else {
+            break;
+        }
+    }
+    rest.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+}
+
+async fn collect_rows<'e, E>(q: PgQuery<'_>, executor: E) -> anyhow::Result<Vec<Value>>
+where
+    E: sqlx::PgExecutor<'e>,
+{
+    let mut result: Vec<Value> = vec![];
+    let mut stream = q.fetch(executor);
+    while let Some(res) = stream.next().await {
+        result.push(row_to_json(&(res?)));
+    }
+    Ok(result)
+}
+
+/// Json object with the values of the row, keyed by column name.
+pub fn row_to_json(row: &PgRow) -> Value {
+    let mut obj = json!({});
+    row.columns().iter().for_each(|col| {
+        let value = get_col_value(row, col);
+        obj[col.name()] = value;
+    });
+    obj
+}
+
+type PgQuery<'q> = sqlx::query::Query<'q, sqlx::Postgres, sqlx::postgres::PgArguments>;
+
+/// Binds a Json value according to its type.
+/// Arrays and objects are bound as `json`.
+fn bind_param<'q>(q: PgQuery<'q>, param: &Value) -> PgQuery<'q> {
+    match param {
+        Value::Null => q.bind(None::<String>),
+        Value::Bool(b) => q.bind(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => q.bind(i),
+            None => q.bind(n.as_f64()),
+        },
+        Value::String(s) => q.bind(s.clone()),
+        _ => q.bind(sqlx::types::Json(param.clone())),
+    }
+}
+
 /// A very small set of types are handled, some in an ad hoc way.
 /// Expand/refine to your heart's content.
 fn get_col_value(row: &PgRow, col: &PgColumn) -> Value {
@@ -73,7 +217,7 @@ where
         }
         Ok(None) => json!(null),
         Err(e) => {
-            log::error!("{}", e);
+            tracing::error!("{}", e);
             json!(null)
         }
     }
@@ -90,7 +234,7 @@ where
         }
         Ok(None) => json!(null),
         Err(e) => {
-            log::error!("{}", e);
+            tracing::error!("{}", e);
             json!(null)
         }
     }
@@ -101,7 +245,7 @@ fn value_as_bool(row: &PgRow, name: &str) -> Value {
         Ok(Some(val)) => json!(val),
         Ok(None) => json!(null),
         Err(e) => {
-            log::error!("{}", e);
+            tracing::error!("{}", e);
             json!(null)
         }
     }
@@ -125,7 +269,7 @@ fn bytea_value(row: &PgRow, name: &str) -> Value {
         Ok(Some(val)) => json!(bytea_as_string(&val)),
         Ok(None) => json!(null),
         Err(e) => {
-            log::error!("{}", e);
+            tracing::error!("{}", e);
             json!(null)
         }
     }
@@ -150,3 +294,37 @@ fn bytea_as_string(val: &[u8]) -> String {
         .join(", ");
     format!("({len}) [{elements}{suffix_vec}] -> ascii='{ascii}{suffix_str}'")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_not_changing_settings_are_accepted() {
+        for query in [
+            "select * from usr",
+            "update usr set name = 'x' where user_id = $1",
+            "with s as (select 1) select * from s",
+            "select current_setting('request.user_id', true)",
+            "-- set role\nselect 1",
+        ] {
+            assert!(check_settings(query).is_ok(), "{query}");
+        }
+    }
+
+    #[test]
+    fn queries_changing_settings_are_rejected() {
+        for query in [
+            "select set_config('request.roles', 'admin', true)",
+            "select PG_CATALOG.SET_CONFIG('role', 'none', true), count(*) from usr",
+            "select query_to_xml('select 1', true, true, '')",
+            "SET ROLE none",
+            "reset role",
+            "  /* comment */ ( set request.roles = 'admin')",
+            "-- comment\nset request.user_id = 'x'",
+            "do $$ begin perform 1; end $$",
+        ] {
+            assert!(check_settings(query).is_err(), "{query}");
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::db::traced;
+use crate::models::{Group, User};
+
+#[tracing::instrument(skip_all)]
+pub async fn get_groups(pool: &sqlx::PgPool) -> anyhow::Result<Vec<Group>> {
+    let groups = traced!(
+        sqlx::query_as!(Group, "select * from user_group order by name"),
+        fetch_all(pool)
+    )?;
+    Ok(groups)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_group(
+    pool: &sqlx::PgPool,
+    group_id: &uuid::Uuid,
+) -> anyhow::Result<Option<Group>> {
+    let group = traced!(
+        sqlx::query_as!(
+            Group,
+            "select * from user_group where group_id = $1",
+            group_id
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(group)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_group(
+    pool: &sqlx::PgPool,
+    name: &str,
+    description: Option<&str>,
+) -> anyhow::Result<Group> {
+    let group = traced!(
+        sqlx::query_as!(
+            Group,
+            "insert into user_group (name, description) values ($1, $2) returning *",
+            name,
+            description,
+        ),
+        fetch_one(pool)
+    )?;
+    Ok(group)
+}
+
+/// Updates the given fields of the group.
+/// Returns `None` if there is no such group.
+#[tracing::instrument(skip_all)]
+pub async fn update_group(
+    pool: &sqlx::PgPool,
+    group_id: &uuid::Uuid,
+    name: Option<&str>,
+    description: Option<&str>,
+) -> anyhow::Result<Option<Group>> {
+    let group = traced!(
+        sqlx::query_as!(
+            Group,
+            r#"
+            update user_group
+             set name = coalesce($1, user_group.name),
+                 description = coalesce($2, user_group.description)
+            where group_id = $3
+            returning *
+        "#,
+            name,
+            description,
+            group_id,
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(group)
+}
+
+/// Deletes the group, along with its memberships.
+/// Returns `None` if there is no such group.
+#[tracing::instrument(skip_all)]
+pub async fn delete_group(
+    pool: &sqlx::PgPool,
+    group_id: &uuid::Uuid,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    let record = traced!(
+        sqlx::query!(
+            "delete from user_group where group_id = $1 returning group_id",
+            group_id
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(record.map(|r| r.group_id))
+}
+
+/// The (not deleted) members of the group.
+#[tracing::instrument(skip_all)]
+pub async fn get_members(pool: &sqlx::PgPool, group_id: &uuid::Uuid) -> anyhow::Result<Vec<User>> {
+    let users = traced!(
+        sqlx::query_as!(
+            User,
+            r#"
+            select u.*
+            from usr u
+                join group_member gm on gm.user_id = u.user_id
+            where gm.group_id = $1
+              and u.deleted_at is null
+            order by u.email
+        "#,
+            group_id,
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(users)
+}
+
+/// The groups the user is a member of.
+#[tracing::instrument(skip_all)]
+pub async fn get_user_groups(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Vec<Group>> {
+    let groups = traced!(
+        sqlx::query_as!(
+            Group,
+            r#"
+            select g.*
+            from user_group g
+                join group_member gm on gm.group_id = g.group_id
+            where gm.user_id = $1
+            order by g.name
+        "#,
+            user_id,
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(groups)
+}
+
+/// Adds the user to the group.
+/// Returns `false` if the user was already a member.
+#[tracing::instrument(skip_all)]
+pub async fn add_member(
+    pool: &sqlx::PgPool,
+    group_id: &uuid::Uuid,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<bool> {
+    let res = traced!(
+        sqlx::query!(
+            r#"
+            insert into group_member (group_id, user_id) values ($1, $2)
+            on conflict do nothing
+        "#,
+            group_id,
+            user_id,
+        ),
+        execute(pool)
+    )?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Removes the user from the group.
+/// Returns `false` if the user was not a member.
+#[tracing::instrument(skip_all)]
+pub async fn remove_member(
+    pool: &sqlx::PgPool,
+    group_id: &uuid::Uuid,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<bool> {
+    let res = traced!(
+        sqlx::query!(
+            "delete from group_member where group_id = $1 and user_id = $2",
+            group_id,
+            user_id,
+        ),
+        execute(pool)
+    )?;
+    Ok(res.rows_affected() > 0)
+}
@@ -0,0 +1,59 @@
+//! Checks of the database for the health and readiness of the service.
+
+use std::time::{Duration, Instant};
+
+use sqlx::migrate::Migrator;
+
+/// The migrations in `./migrations`, embedded at build time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+pub struct DatabaseInfo {
+    /// Of a `select 1`.
+    pub latency: Duration,
+    /// `server_version` of Postgres.
+    pub server_version: String,
+}
+
+/// Runs a `select 1`, and gets the server version, failing if not done within `timeout`.
+#[tracing::instrument(skip_all)]
+pub async fn check_database(
+    pool: &sqlx::PgPool,
+    timeout: Duration,
+) -> anyhow::Result<DatabaseInfo> {
+    let check = async {
+        let start = Instant::now();
+        sqlx::query("select 1").execute(pool).await?;
+        let latency = start.elapsed();
+        let server_version: String = sqlx::query_scalar("select current_setting('server_version')")
+            .fetch_one(pool)
+            .await?;
+        anyhow::Ok(DatabaseInfo {
+            latency,
+            server_version,
+        })
+    };
+    tokio::time::timeout(timeout, check)
+        .await
+        .map_err(|_| anyhow::anyhow!("no response from the database within {timeout:?}"))?
+}
+
+/// The embedded migrations not successfully applied to the database (all of them if
+/// it was never migrated), as `<version>_<description>`.
+#[tracing::instrument(skip_all)]
+pub async fn pending_migrations(pool: &sqlx::PgPool) -> anyhow::Result<Vec<String>> {
+    let migrated: bool = sqlx::query_scalar("select to_regclass('_sqlx_migrations') is not null")
+        .fetch_one(pool)
+        .await?;
+    let applied: Vec<i64> = if migrated {
+        sqlx::query_scalar("select version from _sqlx_migrations where success")
+            .fetch_all(pool)
+            .await?
+    } else {
+        vec![]
+    };
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(|m| format!("{}_{}", m.version, m.description.replace(' ', "_")))
+        .collect())
+}
@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+/// A generic query execution, as recorded in the history.
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct QueryRecord {
+    /// When the query was executed.
+    #[schema(value_type = str)]
+    pub executed_at: DateTime<Utc>,
+
+    /// The query text (after unescaping).
+    pub query: String,
+
+    /// Parameters bound to the query, if any.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(value_type = Vec<Object>)]
+    pub params: Vec<Value>,
+
+    /// Who submitted the query, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<String>,
+
+    /// Duration of the execution in milliseconds.
+    pub duration_ms: f64,
+
+    /// Number of rows returned, if the query succeeded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_count: Option<usize>,
+
+    /// Error message, if the query failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl QueryRecord {
+    pub fn new(
+        query: &str,
+        params: &[Value],
+        caller: Option<String>,
+        elapsed: Duration,
+        res: &anyhow::Result<Value>,
+    ) -> Self {
+        let (row_count, error) = match res {
+            Ok(res) => (res["result"].as_array().map(|a| a.len()), None),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        QueryRecord {
+            executed_at: Utc::now(),
+            query: crate::common::unescape_query(query),
+            params: params.to_vec(),
+            caller,
+            duration_ms: elapsed.as_secs_f64() * 1000.0,
+            row_count,
+            error,
+        }
+    }
+}
+
+/// Criteria to select entries from the history.
+#[derive(clap::Args, Deserialize, IntoParams, Clone, Debug, Default)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryFilter {
+    /// Only queries containing this text (case-insensitive).
+    #[clap(long)]
+    pub contains: Option<String>,
+
+    /// Only queries submitted by this caller.
+    #[clap(long)]
+    pub caller: Option<String>,
+
+    /// Only queries executed at or after this time (RFC 3339).
+    #[clap(long)]
+    #[param(value_type = Option<str>)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only failed queries.
+    #[clap(long)]
+    #[serde(default)]
+    pub errors: bool,
+
+    /// Maximum number of entries to report, most recent first. By default, 20.
+    #[clap(long)]
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn accepts(&self, rec: &QueryRecord) -> bool {
+        if let Some(contains) = &self.contains {
+            if !rec.query.to_lowercase().contains(&contains.to_lowercase()) {
+                return false;
+            }
+        }
+        if self.caller.is_some() && rec.caller != self.caller {
+            return false;
+        }
+        if let Some(since) = &self.since {
+            if rec.executed_at < *since {
+                return false;
+            }
+        }
+        !self.errors || rec.error.is_some()
+    }
+}
+
+/// Append-only JSONL file with the generic query executions.
+/// Once it reaches `max_bytes`, it is renamed with the `.1` suffix (replacing the previous one),
+/// so the history takes at most about twice that, and reading it is bounded.
+/// The file is accessed in blocking threads, not to hold up the async runtime.
+pub struct History {
+    path: PathBuf,
+    max_bytes: u64,
+    lock: Arc<Mutex<()>>,
+}
+
+impl History {
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        History {
+            path,
+            max_bytes,
+            lock: Arc::default(),
+        }
+    }
+
+    /// Appends the record to the history file.
+    /// Failures are only logged, so they don't affect the query response.
+    pub async fn record(&self, rec: &QueryRecord) {
+        if let Err(e) = self.append(rec).await {
+            tracing::error!("Error recording query in {:?}: {}", self.path, e);
+        }
+    }
+
+    async fn append(&self, rec: &QueryRecord) -> anyhow::Result<()> {
+        let line = serde_json::to_string(rec)?;
+        let (path, max_bytes, lock) = (self.path.clone(), self.max_bytes, self.lock.clone());
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap();
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            writeln!(file, "{line}")?;
+            if file.metadata()?.len() >= max_bytes {
+                std::fs::rename(&path, rotated_path(&path))?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    /// Gets the entries accepted by the filter, most recent first.
+    pub async fn read(&self, filter: &HistoryFilter) -> anyhow::Result<Vec<QueryRecord>> {
+        let (path, lock, filter) = (self.path.clone(), self.lock.clone(), filter.clone());
+        tokio::task::spawn_blocking(move || {
+            let _guard = lock.lock().unwrap();
+            let limit = filter.limit.unwrap_or(20);
+            let mut records = read_file(&path, &filter, limit)?;
+            if records.len() < limit {
+                let older = read_file(&rotated_path(&path), &filter, limit - records.len())?;
+                records.extend(older);
+            }
+            Ok(records)
+        })
+        .await?
+    }
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    rotated.into()
+}
+
+/// The last `limit` entries of the file accepted by the filter, most recent first.
+fn read_file(
+    path: &Path,
+    filter: &HistoryFilter,
+    limit: usize,
+) -> anyhow::Result<Vec<QueryRecord>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut records = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str::<QueryRecord>(&line) {
+            Ok(rec) if filter.accepts(&rec) => records.push(rec),
+            Ok(_) => (),
+            Err(e) => tracing::warn!("Skipping malformed history entry: {}", e),
+        }
+    }
+    records.reverse();
+    records.truncate(limit);
+    Ok(records)
+}
@@ -0,0 +1,178 @@
+//! Identity of the caller of the current request, propagated to Postgres as the settings
+//! `request.user_id` and `request.roles`, so row-level security policies (and the row history)
+//! can refer to them with `current_setting(...)`.
+//!
+//! The identity is kept in a task-local for the duration of the request. The statements that
+//! need it (the writes to `usr`, the imports, and the generic queries) run in a transaction
+//! from [`begin`], which sets it with `set_config(..., true)`: the settings end with the
+//! transaction, so no connection keeps the identity of a previous request, and nothing is
+//! sent on the other checkouts (e.g., of the CLI or the health checks).
+//! The typed reads of the service run as the owner of the tables, to which RLS does not apply.
+
+use std::future::Future;
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::db::pool;
+
+/// The caller, as given to Postgres.
+#[derive(Clone, Debug, Default)]
+pub struct Identity {
+    /// For `request.user_id`.
+    pub user_id: String,
+    /// For `request.roles`, comma-separated.
+    pub roles: Vec<String>,
+}
+
+tokio::task_local! {
+    static IDENTITY: Identity;
+}
+
+/// Runs the future (e.g., the handling of a request) with the given identity.
+pub async fn scope<F: Future>(identity: Identity, f: F) -> F::Output {
+    IDENTITY.scope(identity, f).await
+}
+
+/// The identity of the current request, if any.
+pub fn current() -> Option<Identity> {
+    IDENTITY.try_with(Identity::clone).ok()
+}
+
+/// Begins a transaction with the settings of the current identity, if any, local to it.
+pub async fn begin(pool: &PgPool) -> sqlx::Result<Transaction<'static, Postgres>> {
+    let mut tx = pool::begin(pool).await?;
+    if let Some(identity) = current() {
+        sqlx::query(
+            "select set_config('request.user_id', $1, true), set_config('request.roles', $2, true)",
+        )
+        .bind(identity.user_id)
+        .bind(identity.roles.join(","))
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(tx)
+}
+
+/// These need the database of `DATABASE_URL`, migrated, and connecting as a role able to
+/// create roles.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{generic, users};
+    use crate::server::database::UserPostReq;
+
+    /// Not the owner of `usr`, so its policies apply.
+    const READER: &str = "sqlxum_test_reader";
+
+    async fn pool() -> PgPool {
+        dotenvy::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL for the tests");
+        PgPool::connect(&url).await.unwrap()
+    }
+
+    async fn insert_user(pool: &PgPool) -> uuid::Uuid {
+        let req = UserPostReq {
+            email: format!("{}@identity.test", uuid::Uuid::new_v4()),
+            name: "Identity Test".to_string(),
+        };
+        users::insert_user(pool, &req).await.unwrap().user_id
+    }
+
+    fn identity(user_id: &str, roles: &[&str]) -> Identity {
+        Identity {
+            user_id: user_id.to_string(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn changed_by_is_the_identity_or_the_database_user() {
+        let pool = pool().await;
+        let user_id = insert_user(&pool).await;
+        scope(identity("tester", &[]), async {
+            users::update_user(&pool, &user_id, None, Some("Renamed"), &Default::default())
+                .await
+                .unwrap()
+                .unwrap();
+        })
+        .await;
+
+        let changed_by: Vec<(String, String)> = sqlx::query_as(
+            "select operation, changed_by from row_history
+             where table_name = 'usr' and row_id = $1 order by history_id",
+        )
+        .bind(user_id.to_string())
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let current_user: String = sqlx::query_scalar("select current_user::text")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            changed_by,
+            [
+                ("insert".to_string(), current_user),
+                ("update".to_string(), "tester".to_string())
+            ]
+        );
+
+        // Not kept by the connection after the transaction.
+        let setting: Option<String> =
+            sqlx::query_scalar("select current_setting('request.user_id', true)")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_ne!(setting.as_deref(), Some("tester"));
+    }
+
+    #[tokio::test]
+    async fn usr_self_applies_to_a_db_role_other_than_the_owner() {
+        let pool = pool().await;
+        sqlx::query(&format!(
+            "do $$ begin create role {READER}; exception when duplicate_object then null; end $$"
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(&format!("grant select on usr to {READER}"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        let user_id = insert_user(&pool).await;
+        let other_id = insert_user(&pool).await;
+
+        let visible = |identity: Option<Identity>| {
+            let pool = pool.clone();
+            async move {
+                let params = [user_id.to_string().into(), other_id.to_string().into()];
+                let query = generic::do_query(
+                    &pool,
+                    "select user_id::text from usr where user_id in ($1::uuid, $2::uuid)",
+                    &params,
+                    true,
+                    Some(READER),
+                );
+                let rows = match identity {
+                    Some(identity) => scope(identity, query).await,
+                    None => query.await,
+                };
+                let mut ids: Vec<String> = rows.unwrap()["result"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|row| row["user_id"].as_str().unwrap().to_string())
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        let user = user_id.to_string();
+        let mut both = vec![user.clone(), other_id.to_string()];
+        both.sort();
+        assert_eq!(visible(Some(identity(&user, &[]))).await, [user.as_str()]);
+        assert_eq!(visible(Some(identity(&user, &["admin"]))).await, both);
+        assert!(visible(None).await.is_empty());
+    }
+}
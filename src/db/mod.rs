@@ -1,7 +1,96 @@
+pub(crate) mod api_keys;
+pub(crate) mod bulk;
 pub(crate) mod dispatch;
+pub(crate) mod filter;
 pub(crate) mod generic;
+pub(crate) mod groups;
+pub(crate) mod health;
+pub(crate) mod history;
+pub(crate) mod identity;
+pub(crate) mod passwords;
+pub(crate) mod pool;
+pub(crate) mod roles;
+pub(crate) mod row_history;
+pub(crate) mod schema;
+pub(crate) mod sessions;
+pub(crate) mod tables;
 pub(crate) mod users;
 
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use tracing::Instrument;
+
+use crate::models::Permission;
+
+/// An error due to invalid input, like an unknown column in a filter,
+/// as opposed to a failure of the service itself.
+#[derive(Debug)]
+pub struct BadInput(pub String);
+
+impl std::fmt::Display for BadInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BadInput {}
+
+/// A [`BadInput`] error with the given detail.
+pub fn bad_input(detail: impl Into<String>) -> anyhow::Error {
+    BadInput(detail.into()).into()
+}
+
+/// Runs a typed statement (from `sqlx::query!` and the like, or a `QueryBuilder`)
+/// with the given method and executor, in a `sql` span as the generic queries
+/// (see [`run_traced`]), e.g., `traced!(sqlx::query!(...), fetch_one(pool))`.
+macro_rules! traced {
+    ($query:expr, fetch_one($executor:expr)) => {
+        $crate::db::traced!(@run $query, fetch_one($executor), |_| 1)
+    };
+    ($query:expr, fetch_optional($executor:expr)) => {
+        $crate::db::traced!(@run $query, fetch_optional($executor), |r| r.is_some() as u64)
+    };
+    ($query:expr, fetch_all($executor:expr)) => {
+        $crate::db::traced!(@run $query, fetch_all($executor), |r| r.len() as u64)
+    };
+    ($query:expr, execute($executor:expr)) => {
+        $crate::db::traced!(@run $query, execute($executor), |r| r.rows_affected())
+    };
+    (@run $query:expr, $method:ident($executor:expr), $rows:expr) => {{
+        let query = $query;
+        let statement = sqlx::Execute::sql(&query);
+        $crate::db::run_traced(statement, query.$method($executor), $rows).await
+    }};
+}
+pub(crate) use traced;
+
+/// Runs a statement in a `sql` span with the statement, and the rows (as counted by `rows`)
+/// and duration once done.
+pub async fn run_traced<T>(
+    statement: &str,
+    statement_fut: impl Future<Output = sqlx::Result<T>>,
+    rows: impl FnOnce(&T) -> u64,
+) -> sqlx::Result<T> {
+    let span = tracing::info_span!(
+        "sql",
+        db.statement = statement,
+        rows = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    let start = Instant::now();
+    let result = statement_fut.instrument(span.clone()).await?;
+    record_sql_done(&span, rows(&result), start.elapsed());
+    Ok(result)
+}
+
+/// Records the rows and duration of a statement in its `sql` span.
+pub fn record_sql_done(span: &tracing::Span, rows: u64, duration: Duration) {
+    span.record("rows", rows);
+    span.record("duration_ms", duration.as_secs_f64() * 1000.0);
+}
+
 #[derive(clap::Parser, Debug)]
 pub struct DbOpts {
     /// Use own database (to perform migrations)
@@ -11,4 +100,104 @@ pub struct DbOpts {
     /// Run query
     #[clap(long, value_name = "QUERY")]
     query: Option<String>,
+
+    /// Run the query in a read-only transaction
+    #[clap(long)]
+    read_only: bool,
+
+    #[clap(subcommand)]
+    cmd: Option<DbCmd>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DbCmd {
+    /// Show the history of generic queries
+    History(history::HistoryFilter),
+
+    /// List tables and views
+    Tables {
+        /// Only tables in this schema
+        #[clap(long)]
+        schema: Option<String>,
+    },
+
+    /// Describe a table or view
+    Describe {
+        /// Name of the table, optionally schema-qualified, e.g., `public.usr`
+        table: String,
+    },
+
+    /// Import users from a JSON array, NDJSON, or CSV file
+    ImportUsers {
+        /// The file; `-` for standard input
+        file: PathBuf,
+
+        /// By default, according to the file extension, or JSON
+        #[clap(long, value_enum)]
+        format: Option<bulk::Format>,
+
+        /// Insert the valid users even if others fail, reporting the failures
+        #[clap(long)]
+        continue_on_error: bool,
+    },
+
+    /// Restore a deleted user
+    RestoreUser {
+        /// ID of the user
+        user_id: uuid::Uuid,
+    },
+
+    /// Permanently remove deleted users
+    PurgeUsers {
+        /// Only this user
+        #[clap(long, conflicts_with = "deleted_before")]
+        user_id: Option<uuid::Uuid>,
+
+        /// Only users deleted before this time (RFC 3339)
+        #[clap(long)]
+        deleted_before: Option<chrono::DateTime<chrono::Utc>>,
+    },
+
+    /// Manage the keys for the API clients
+    ApiKey {
+        #[clap(subcommand)]
+        cmd: ApiKeyCmd,
+    },
+
+    /// Export all users as a JSON array, NDJSON, or CSV
+    ExportUsers {
+        #[clap(long, value_enum, default_value = "json")]
+        format: bulk::Format,
+
+        /// Write to this file instead of standard output
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ApiKeyCmd {
+    /// Create a key. The key itself is only reported here
+    Create {
+        /// Name to identify the key, e.g., the client using it
+        #[clap(long)]
+        name: String,
+
+        /// Permissions granted directly to the key
+        #[clap(long = "scope", value_enum, required_unless_present = "roles")]
+        scopes: Vec<Permission>,
+
+        /// Roles of the key (by name), for the permissions and database role of each
+        #[clap(long = "role")]
+        roles: Vec<String>,
+    },
+
+    /// List the keys
+    List,
+
+    /// Revoke a key
+    Revoke {
+        /// ID of the key
+        key_id: uuid::Uuid,
+    },
 }
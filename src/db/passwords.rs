@@ -0,0 +1,75 @@
+//! Password credentials of users, hashed with Argon2.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::sync::OnceLock;
+
+/// Hashes the password, in PHC string format.
+/// This is purposely slow, so better called via `spawn_blocking`.
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("error hashing password: {e}"))?;
+    Ok(hash.to_string())
+}
+
+/// Whether the password matches the hash.
+/// This is purposely slow, so better called via `spawn_blocking`.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            tracing::error!("invalid password hash: {e}");
+            false
+        }
+    }
+}
+
+/// A hash of a random password, to verify against when there is no user or password,
+/// taking as long as with one.
+pub fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        hash_password(&crate::common::new_token()).expect("error hashing the dummy password")
+    })
+}
+
+/// The password hash of the user, if a password has been set.
+#[tracing::instrument(skip_all)]
+pub async fn get_password_hash(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Option<String>> {
+    let record = sqlx::query!(
+        "select password_hash from user_password where user_id = $1",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(record.map(|r| r.password_hash))
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn set_password_hash(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    password_hash: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+            insert into user_password (user_id, password_hash) values ($1, $2)
+            on conflict (user_id) do update
+             set password_hash = excluded.password_hash,
+                 updated_at = now()
+        "#,
+        user_id,
+        password_hash,
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,35 @@
+//! Acquisition of connections from the pool, counting the tasks waiting for one
+//! in the `sqlxum_db_pool_waiters` gauge, as the sqlx pool does not report them.
+//! Only the acquisitions made here are counted: the transactions, the generic queries,
+//! the table rows, and the CSV export.
+
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres, Transaction};
+
+const WAITERS: &str = "sqlxum_db_pool_waiters";
+
+/// Counts a waiting task until dropped, including if the acquisition is cancelled.
+struct Waiting;
+
+impl Waiting {
+    fn start() -> Self {
+        metrics::gauge!(WAITERS).increment(1.0);
+        Waiting
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        metrics::gauge!(WAITERS).decrement(1.0);
+    }
+}
+
+pub async fn acquire(pool: &PgPool) -> sqlx::Result<PoolConnection<Postgres>> {
+    let _waiting = Waiting::start();
+    pool.acquire().await
+}
+
+pub async fn begin(pool: &PgPool) -> sqlx::Result<Transaction<'static, Postgres>> {
+    let _waiting = Waiting::start();
+    pool.begin().await
+}
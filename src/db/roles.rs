@@ -0,0 +1,198 @@
+use crate::db::traced;
+use crate::models::{Role, User};
+
+#[tracing::instrument(skip_all)]
+pub async fn get_roles(pool: &sqlx::PgPool) -> anyhow::Result<Vec<Role>> {
+    let roles = traced!(
+        sqlx::query_as!(Role, "select * from role order by name"),
+        fetch_all(pool)
+    )?;
+    Ok(roles)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_role(pool: &sqlx::PgPool, role_id: &uuid::Uuid) -> anyhow::Result<Option<Role>> {
+    let role = traced!(
+        sqlx::query_as!(Role, "select * from role where role_id = $1", role_id),
+        fetch_optional(pool)
+    )?;
+    Ok(role)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn insert_role(
+    pool: &sqlx::PgPool,
+    name: &str,
+    description: Option<&str>,
+    permissions: &[String],
+    db_role: Option<&str>,
+) -> anyhow::Result<Role> {
+    let role = traced!(
+        sqlx::query_as!(
+            Role,
+            r#"
+            insert into role (name, description, permissions, db_role) values ($1, $2, $3, $4)
+            returning *
+        "#,
+            name,
+            description,
+            permissions,
+            db_role,
+        ),
+        fetch_one(pool)
+    )?;
+    Ok(role)
+}
+
+/// Updates the given fields of the role; `db_role` is cleared if `Some(None)`.
+/// Returns `None` if there is no such role.
+#[tracing::instrument(skip_all)]
+pub async fn update_role(
+    pool: &sqlx::PgPool,
+    role_id: &uuid::Uuid,
+    name: Option<&str>,
+    description: Option<&str>,
+    permissions: Option<&[String]>,
+    db_role: Option<Option<&str>>,
+) -> anyhow::Result<Option<Role>> {
+    let role = traced!(
+        sqlx::query_as!(
+            Role,
+            r#"
+            update role
+             set name = coalesce($1, role.name),
+                 description = coalesce($2, role.description),
+                 permissions = coalesce($3, role.permissions),
+                 db_role = case when $4 then $5 else role.db_role end
+            where role_id = $6
+            returning *
+        "#,
+            name,
+            description,
+            permissions,
+            db_role.is_some(),
+            db_role.flatten(),
+            role_id,
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(role)
+}
+
+/// Deletes the role, along with its assignments.
+/// Returns `None` if there is no such role.
+#[tracing::instrument(skip_all)]
+pub async fn delete_role(
+    pool: &sqlx::PgPool,
+    role_id: &uuid::Uuid,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    let record = traced!(
+        sqlx::query!(
+            "delete from role where role_id = $1 returning role_id",
+            role_id
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(record.map(|r| r.role_id))
+}
+
+/// The (not deleted) users with the role.
+#[tracing::instrument(skip_all)]
+pub async fn get_role_users(
+    pool: &sqlx::PgPool,
+    role_id: &uuid::Uuid,
+) -> anyhow::Result<Vec<User>> {
+    let users = traced!(
+        sqlx::query_as!(
+            User,
+            r#"
+            select u.*
+            from usr u
+                join user_role ur on ur.user_id = u.user_id
+            where ur.role_id = $1
+              and u.deleted_at is null
+            order by u.email
+        "#,
+            role_id,
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(users)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn get_user_roles(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Vec<Role>> {
+    let roles = traced!(
+        sqlx::query_as!(
+            Role,
+            r#"
+            select r.*
+            from role r
+                join user_role ur on ur.role_id = r.role_id
+            where ur.user_id = $1
+            order by r.name
+        "#,
+            user_id,
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(roles)
+}
+
+/// The roles with the given names, ignoring any unknown.
+#[tracing::instrument(skip_all)]
+pub async fn get_roles_by_name(pool: &sqlx::PgPool, names: &[String]) -> anyhow::Result<Vec<Role>> {
+    let roles = traced!(
+        sqlx::query_as!(
+            Role,
+            "select * from role where name = any($1) order by name",
+            names,
+        ),
+        fetch_all(pool)
+    )?;
+    Ok(roles)
+}
+
+/// Assigns the role to the user.
+/// Returns `false` if the user already had the role.
+#[tracing::instrument(skip_all)]
+pub async fn add_user_role(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    role_id: &uuid::Uuid,
+) -> anyhow::Result<bool> {
+    let res = traced!(
+        sqlx::query!(
+            r#"
+            insert into user_role (user_id, role_id) values ($1, $2)
+            on conflict do nothing
+        "#,
+            user_id,
+            role_id,
+        ),
+        execute(pool)
+    )?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Removes the role from the user.
+/// Returns `false` if the user did not have the role.
+#[tracing::instrument(skip_all)]
+pub async fn remove_user_role(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    role_id: &uuid::Uuid,
+) -> anyhow::Result<bool> {
+    let res = traced!(
+        sqlx::query!(
+            "delete from user_role where user_id = $1 and role_id = $2",
+            user_id,
+            role_id,
+        ),
+        execute(pool)
+    )?;
+    Ok(res.rows_affected() > 0)
+}
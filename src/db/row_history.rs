@@ -0,0 +1,64 @@
+//! Change history of the rows of the tables registered with `trigger_row_history`
+//! (see `migrations/4_row_history.sql`).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+
+/// A change to a row, as recorded in the `row_history` table.
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct RowChange {
+    /// One of `insert`, `update`, `delete`.
+    pub operation: String,
+    /// The row before the change, except for `insert`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub old_row: Option<Value>,
+    /// The row after the change, except for `delete`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub new_row: Option<Value>,
+    /// Columns with a different value after an `update`.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed_columns: Vec<String>,
+    /// The `request.user_id` setting at the time of the change, or else the database user.
+    pub changed_by: String,
+    #[schema(value_type = str)]
+    pub changed_at: DateTime<Utc>,
+}
+
+/// The changes to the given row, oldest first.
+#[tracing::instrument(skip_all)]
+pub async fn get_row_history(
+    pool: &sqlx::PgPool,
+    table: &str,
+    row_id: &str,
+) -> anyhow::Result<Vec<RowChange>> {
+    let mut changes: Vec<RowChange> = sqlx::query_as(
+        r#"
+            select operation, old_row, new_row, changed_by, changed_at
+            from row_history
+            where table_name = $1 and row_id = $2
+            order by changed_at, history_id
+        "#,
+    )
+    .bind(table)
+    .bind(row_id)
+    .fetch_all(pool)
+    .await?;
+
+    for change in &mut changes {
+        if let (Some(Value::Object(old)), Some(Value::Object(new))) =
+            (&change.old_row, &change.new_row)
+        {
+            change.changed_columns = new
+                .iter()
+                .filter(|(col, value)| old.get(*col) != Some(value))
+                .map(|(col, _)| col.clone())
+                .collect();
+        }
+    }
+    Ok(changes)
+}
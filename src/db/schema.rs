@@ -0,0 +1,260 @@
+//! Introspection of the database structure via `pg_catalog`.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct SchemaInfo {
+    pub name: String,
+    pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct TableInfo {
+    pub schema: String,
+    pub name: String,
+    /// One of `table`, `partitioned table`, `view`, `materialized view`, `foreign table`.
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// Type as reported by `format_type`, e.g., `timestamp with time zone`.
+    pub data_type: String,
+    pub nullable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// A column along with the table it belongs to.
+#[derive(sqlx::FromRow, Debug)]
+pub struct TableColumn {
+    pub schema: String,
+    pub table: String,
+    #[sqlx(flatten)]
+    pub column: ColumnInfo,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct KeyInfo {
+    pub name: String,
+    pub columns: Vec<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct ForeignKeyInfo {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub referenced_schema: String,
+    pub referenced_table: String,
+    pub referenced_columns: Vec<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct IndexInfo {
+    pub name: String,
+    pub unique: bool,
+    pub primary: bool,
+    /// As reported by `pg_get_indexdef`.
+    pub definition: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct TableDescription {
+    #[serde(flatten)]
+    pub table: TableInfo,
+    pub columns: Vec<ColumnInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub primary_key: Option<KeyInfo>,
+    pub foreign_keys: Vec<ForeignKeyInfo>,
+    pub indexes: Vec<IndexInfo>,
+}
+
+/// Excludes the system schemas.
+const USER_SCHEMAS: &str = r#"n.nspname not like 'pg\_%' and n.nspname <> 'information_schema'"#;
+
+#[tracing::instrument(skip_all)]
+pub async fn get_schemas(pool: &sqlx::PgPool) -> anyhow::Result<Vec<SchemaInfo>> {
+    let query = format!(
+        r#"
+            select n.nspname::text as name,
+                   pg_get_userbyid(n.nspowner)::text as owner,
+                   obj_description(n.oid, 'pg_namespace') as comment
+            from pg_namespace n
+            where {USER_SCHEMAS}
+            order by 1
+        "#
+    );
+    Ok(sqlx::query_as(&query).fetch_all(pool).await?)
+}
+
+/// Tables, views, etc., in the given schema, or in all user schemas.
+#[tracing::instrument(skip_all)]
+pub async fn get_tables(
+    pool: &sqlx::PgPool,
+    schema: Option<&str>,
+) -> anyhow::Result<Vec<TableInfo>> {
+    let query = format!(
+        r#"
+            select n.nspname::text as schema,
+                   c.relname::text as name,
+                   case c.relkind
+                       when 'r' then 'table'
+                       when 'p' then 'partitioned table'
+                       when 'v' then 'view'
+                       when 'm' then 'materialized view'
+                       when 'f' then 'foreign table'
+                   end as kind,
+                   obj_description(c.oid, 'pg_class') as comment
+            from pg_class c
+                join pg_namespace n on n.oid = c.relnamespace
+            where c.relkind in ('r', 'p', 'v', 'm', 'f')
+              and {USER_SCHEMAS}
+              and ($1::text is null or n.nspname = $1)
+            order by 1, 2
+        "#
+    );
+    Ok(sqlx::query_as(&query).bind(schema).fetch_all(pool).await?)
+}
+
+/// Columns of all the tables, views, etc., in the user schemas.
+#[tracing::instrument(skip_all)]
+pub async fn get_all_columns(pool: &sqlx::PgPool) -> anyhow::Result<Vec<TableColumn>> {
+    let query = format!(
+        r#"
+            select n.nspname::text as schema,
+                   c.relname::text as table,
+                   a.attname::text as name,
+                   format_type(a.atttypid, a.atttypmod) as data_type,
+                   not a.attnotnull as nullable,
+                   pg_get_expr(d.adbin, d.adrelid) as default,
+                   col_description(a.attrelid, a.attnum) as comment
+            from pg_attribute a
+                join pg_class c on c.oid = a.attrelid
+                join pg_namespace n on n.oid = c.relnamespace
+                left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum
+            where c.relkind in ('r', 'p', 'v', 'm', 'f')
+              and {USER_SCHEMAS}
+              and a.attnum > 0
+              and not a.attisdropped
+            order by 1, 2, a.attnum
+        "#
+    );
+    Ok(sqlx::query_as(&query).fetch_all(pool).await?)
+}
+
+/// Full description of a table or view, or `None` if it does not exist.
+#[tracing::instrument(skip_all)]
+pub async fn describe_table(
+    pool: &sqlx::PgPool,
+    schema: &str,
+    name: &str,
+) -> anyhow::Result<Option<TableDescription>> {
+    let tables = get_tables(pool, Some(schema)).await?;
+    let Some(table) = tables.into_iter().find(|t| t.name == name) else {
+        return Ok(None);
+    };
+    let relation = format!("{}.{}", quote_ident(schema), quote_ident(name));
+
+    let columns = sqlx::query_as(
+        r#"
+            select a.attname::text as name,
+                   format_type(a.atttypid, a.atttypmod) as data_type,
+                   not a.attnotnull as nullable,
+                   pg_get_expr(d.adbin, d.adrelid) as default,
+                   col_description(a.attrelid, a.attnum) as comment
+            from pg_attribute a
+                left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum
+            where a.attrelid = $1::regclass
+              and a.attnum > 0
+              and not a.attisdropped
+            order by a.attnum
+        "#,
+    )
+    .bind(&relation)
+    .fetch_all(pool)
+    .await?;
+
+    let primary_key = sqlx::query_as(
+        r#"
+            select con.conname::text as name,
+                   array(select a.attname::text
+                         from unnest(con.conkey) with ordinality k(num, ord)
+                             join pg_attribute a on a.attrelid = con.conrelid and a.attnum = k.num
+                         order by k.ord) as columns
+            from pg_constraint con
+            where con.conrelid = $1::regclass
+              and con.contype = 'p'
+        "#,
+    )
+    .bind(&relation)
+    .fetch_optional(pool)
+    .await?;
+
+    let foreign_keys = sqlx::query_as(
+        r#"
+            select con.conname::text as name,
+                   array(select a.attname::text
+                         from unnest(con.conkey) with ordinality k(num, ord)
+                             join pg_attribute a on a.attrelid = con.conrelid and a.attnum = k.num
+                         order by k.ord) as columns,
+                   fn.nspname::text as referenced_schema,
+                   fc.relname::text as referenced_table,
+                   array(select a.attname::text
+                         from unnest(con.confkey) with ordinality k(num, ord)
+                             join pg_attribute a on a.attrelid = con.confrelid and a.attnum = k.num
+                         order by k.ord) as referenced_columns
+            from pg_constraint con
+                join pg_class fc on fc.oid = con.confrelid
+                join pg_namespace fn on fn.oid = fc.relnamespace
+            where con.conrelid = $1::regclass
+              and con.contype = 'f'
+            order by 1
+        "#,
+    )
+    .bind(&relation)
+    .fetch_all(pool)
+    .await?;
+
+    let indexes = sqlx::query_as(
+        r#"
+            select ic.relname::text as name,
+                   i.indisunique as unique,
+                   i.indisprimary as primary,
+                   pg_get_indexdef(i.indexrelid) as definition
+            from pg_index i
+                join pg_class ic on ic.oid = i.indexrelid
+            where i.indrelid = $1::regclass
+            order by 1
+        "#,
+    )
+    .bind(&relation)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(Some(TableDescription {
+        table,
+        columns,
+        primary_key,
+        foreign_keys,
+        indexes,
+    }))
+}
+
+/// Quotes an SQL identifier, doubling any embedded double quotes.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Splits a possibly schema-qualified name, e.g., `public.usr`.
+/// The schema defaults to `public`.
+pub fn split_table_name(name: &str) -> (&str, &str) {
+    name.split_once('.').unwrap_or(("public", name))
+}
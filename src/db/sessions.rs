@@ -0,0 +1,135 @@
+//! Login sessions, stored in the database with only a hash of their token.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::common::{hash_token, new_token};
+
+#[derive(sqlx::FromRow, Serialize, ToSchema, Debug)]
+pub struct Session {
+    #[schema(value_type = str)]
+    pub session_id: uuid::Uuid,
+    #[schema(value_type = str)]
+    pub user_id: uuid::Uuid,
+    #[schema(value_type = str)]
+    pub created_at: DateTime<Utc>,
+    #[schema(value_type = str)]
+    pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+}
+
+/// Creates a session for the user, returning it along with its token.
+/// Expired sessions of the user are removed.
+#[tracing::instrument(skip_all)]
+pub async fn create_session(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    ttl: Duration,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
+) -> anyhow::Result<(Session, String)> {
+    sqlx::query!(
+        "delete from user_session where user_id = $1 and expires_at <= now()",
+        user_id
+    )
+    .execute(pool)
+    .await?;
+
+    let token = new_token();
+    let expires_at = Utc::now() + chrono::Duration::from_std(ttl)?;
+    let session = sqlx::query_as!(
+        Session,
+        r#"
+            insert into user_session (user_id, token_hash, expires_at, user_agent, ip)
+            values ($1, $2, $3, $4, $5)
+            returning session_id, user_id, created_at, expires_at, user_agent, ip
+        "#,
+        user_id,
+        hash_token(&token),
+        expires_at,
+        user_agent,
+        ip,
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok((session, token))
+}
+
+/// The unexpired session with the given token, if any, as long as its user is not deleted.
+#[tracing::instrument(skip_all)]
+pub async fn get_session_by_token(
+    pool: &sqlx::PgPool,
+    token: &str,
+) -> anyhow::Result<Option<Session>> {
+    let session = sqlx::query_as!(
+        Session,
+        r#"
+            select s.session_id, s.user_id, s.created_at, s.expires_at, s.user_agent, s.ip
+            from user_session s
+                join usr u on u.user_id = s.user_id
+            where s.token_hash = $1
+              and s.expires_at > now()
+              and u.deleted_at is null
+        "#,
+        hash_token(token),
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(session)
+}
+
+/// The unexpired sessions of the user, most recent first.
+#[tracing::instrument(skip_all)]
+pub async fn get_user_sessions(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Vec<Session>> {
+    let sessions = sqlx::query_as!(
+        Session,
+        r#"
+            select session_id, user_id, created_at, expires_at, user_agent, ip
+            from user_session
+            where user_id = $1
+              and expires_at > now()
+            order by created_at desc
+        "#,
+        user_id,
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(sessions)
+}
+
+/// Returns `false` if there is no such session of the user.
+#[tracing::instrument(skip_all)]
+pub async fn revoke_session(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    session_id: &uuid::Uuid,
+) -> anyhow::Result<bool> {
+    let res = sqlx::query!(
+        "delete from user_session where user_id = $1 and session_id = $2",
+        user_id,
+        session_id,
+    )
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+/// Revokes all sessions of the user, returning how many were revoked.
+#[tracing::instrument(skip_all)]
+pub async fn revoke_user_sessions(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<u64> {
+    let res = sqlx::query!("delete from user_session where user_id = $1", user_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected())
+}
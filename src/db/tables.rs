@@ -0,0 +1,149 @@
+//! Generic read-only access to any table or view in the database.
+
+use futures::TryStreamExt;
+use serde_json::{json, Value};
+use sqlx::{Postgres, QueryBuilder};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::db::bad_input;
+use crate::db::filter::{self, Filter};
+use crate::db::generic::{begin_as, row_to_json};
+use crate::db::schema::{self, quote_ident, ColumnInfo};
+
+/// Tables with credentials (even if hashed), never available for generic access.
+const CREDENTIAL_TABLES: [&str; 3] = ["api_key", "user_password", "user_session"];
+
+/// The tables and views available for generic access, with their columns.
+pub struct Catalog {
+    tables: HashMap<(String, String), Vec<ColumnInfo>>,
+}
+
+impl Catalog {
+    #[tracing::instrument(skip_all)]
+    pub async fn load(pool: &sqlx::PgPool) -> anyhow::Result<Self> {
+        let mut tables: HashMap<_, Vec<ColumnInfo>> = HashMap::new();
+        for col in schema::get_all_columns(pool).await? {
+            if col.schema == "public" && CREDENTIAL_TABLES.contains(&col.table.as_str()) {
+                continue;
+            }
+            tables
+                .entry((col.schema, col.table))
+                .or_default()
+                .push(col.column);
+        }
+        tracing::info!("Catalog loaded: {} tables/views", tables.len());
+        Ok(Catalog { tables })
+    }
+
+    pub fn columns(&self, schema: &str, table: &str) -> Option<&[ColumnInfo]> {
+        self.tables
+            .get(&(schema.to_string(), table.to_string()))
+            .map(|v| v.as_slice())
+    }
+}
+
+/// Request for rows of a table, as given by query parameters:
+/// `select`, `order`, `limit`, `offset`, and `<column>=<op>.<value>` filters.
+#[derive(Debug, Default)]
+pub struct TableQuery {
+    pub select: Vec<String>,
+    pub order: Vec<(String, bool)>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub filters: Vec<Filter>,
+}
+
+impl TableQuery {
+    pub fn from_params(params: &[(String, String)]) -> anyhow::Result<Self> {
+        let mut q = TableQuery::default();
+        for (key, value) in params {
+            match key.as_str() {
+                "select" => q.select = split_list(value),
+                "order" => q.order.extend(filter::parse_order(value)),
+                "limit" => q.limit = Some(parse_count("limit", value)?),
+                "offset" => q.offset = Some(parse_count("offset", value)?),
+                _ => q.filters.push(Filter::parse_param(key, value)?),
+            }
+        }
+        Ok(q)
+    }
+}
+
+fn parse_count(name: &str, value: &str) -> anyhow::Result<u32> {
+    value
+        .parse()
+        .map_err(|e| bad_input(format!("invalid {name} '{value}': {e}")))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Gets rows from the table, with the result in the same form as `generic::do_query`,
+/// in a read-only transaction as the caller, as with `generic::do_query`.
+#[tracing::instrument(skip_all)]
+pub async fn query_table(
+    pool: &sqlx::PgPool,
+    db_role: Option<&str>,
+    schema: &str,
+    table: &str,
+    columns: &[ColumnInfo],
+    tq: &TableQuery,
+) -> anyhow::Result<Value> {
+    let data_type = |name: &str| {
+        columns
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.data_type.as_str())
+    };
+    let check_column = |name: &str| match data_type(name) {
+        Some(_) => Ok(quote_ident(name)),
+        None => Err(bad_input(format!("unknown column '{name}'"))),
+    };
+
+    let select = if tq.select.is_empty() {
+        "*".to_string()
+    } else {
+        let cols: anyhow::Result<Vec<_>> = tq.select.iter().map(|c| check_column(c)).collect();
+        cols?.join(", ")
+    };
+
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+        "select {select} from {}.{}",
+        quote_ident(schema),
+        quote_ident(table)
+    ));
+    filter::push_where(&mut qb, &tq.filters, data_type)?;
+
+    for (i, (col, desc)) in tq.order.iter().enumerate() {
+        qb.push(if i == 0 { " order by " } else { ", " });
+        qb.push(check_column(col)?);
+        qb.push(if *desc { " desc" } else { " asc" });
+    }
+    qb.push(" limit ");
+    qb.push_bind(tq.limit.unwrap_or(20) as i64);
+    if let Some(offset) = tq.offset {
+        qb.push(" offset ");
+        qb.push_bind(offset as i64);
+    }
+
+    let start = Instant::now();
+    let query = qb.sql().to_string();
+    tracing::info!("query_table: {query}");
+    let mut tx = begin_as(pool, true, db_role).await?;
+    let rows: Vec<_> = qb.build().fetch(&mut *tx).try_collect().await?;
+    tx.rollback().await?;
+    let result: Vec<Value> = rows.iter().map(row_to_json).collect();
+    let elapsed = format!("{:?}", start.elapsed());
+
+    Ok(json!({
+        "query": query,
+        "result": result,
+        "elapsed": elapsed,
+    }))
+}
@@ -1,25 +1,169 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Postgres, QueryBuilder};
+
 use crate::common::unescape_query;
+use crate::db::filter::{self, Filter};
+use crate::db::{bad_input, identity, traced};
 use crate::models::User;
-use crate::server::database::{UserPostReq, UserPutReq};
+use crate::server::database::UserPostReq;
+
+/// Criteria to get users.
+#[derive(Debug, Default)]
+pub struct UsersQuery {
+    pub filters: Vec<Filter>,
+    /// Condition pasted as is into the query, so it must only come from a trusted source.
+    pub raw_where: Option<String>,
+    /// Columns to order by, with a flag indicating descending order.
+    /// `user_id` is always added as the last key, so the order is stable.
+    pub order_by: Vec<(String, bool)>,
+    pub limit: u32,
+    pub offset: Option<u32>,
+    /// Only users after the one with this ID, according to the order.
+    pub after: Option<uuid::Uuid>,
+    /// Also include (soft) deleted users.
+    pub include_deleted: bool,
+}
+
+impl UsersQuery {
+    /// The order keys, validated, and with the default and `user_id` added as needed.
+    fn order_keys(&self) -> anyhow::Result<Vec<(&str, bool)>> {
+        let mut keys = vec![];
+        for (col, desc) in &self.order_by {
+            if User::column_type(col).is_none() {
+                return Err(bad_input(format!("unknown column '{col}' in order_by")));
+            }
+            if self.after.is_some() && User::is_nullable(col) {
+                return Err(bad_input(format!(
+                    "'after' cannot be used when ordering by nullable column '{col}'"
+                )));
+            }
+            keys.push((col.as_str(), *desc));
+        }
+        if keys.is_empty() {
+            keys.push(("created_at", false));
+        }
+        if !keys.iter().any(|(col, _)| *col == "user_id") {
+            keys.push(("user_id", false));
+        }
+        Ok(keys)
+    }
+
+    /// Whether there are conditions other than the `after` one.
+    fn has_where(&self) -> bool {
+        self.raw_where.is_some() || !self.filters.is_empty() || !self.include_deleted
+    }
+
+    /// Pushes the `where` clause, if any, without the `after` condition.
+    fn push_where(&self, qb: &mut QueryBuilder<'_, Postgres>) -> anyhow::Result<()> {
+        let mut conds = vec![];
+        if !self.include_deleted {
+            conds.push("deleted_at is null".to_string());
+        }
+        if let Some(cond) = &self.raw_where {
+            conds.push(format!("({})", unescape_query(cond)));
+        }
+        if !conds.is_empty() {
+            qb.push(format!(" where {}", conds.join(" and ")));
+            for f in &self.filters {
+                qb.push(" and ");
+                f.push_sql(qb, &User::column_type)?;
+            }
+            Ok(())
+        } else {
+            filter::push_where(qb, &self.filters, User::column_type)
+        }
+    }
+
+    /// Pushes the keyset condition for `after`:
+    /// `(k1 > c.k1) or (k1 = c.k1 and k2 > c.k2) or ...`,
+    /// where `c` is the row of the `after` user, and `>` is `<` for descending keys.
+    fn push_after(
+        &self,
+        qb: &mut QueryBuilder<'_, Postgres>,
+        keys: &[(&str, bool)],
+    ) -> anyhow::Result<()> {
+        let Some(after) = self.after else {
+            return Ok(());
+        };
+        qb.push(if self.has_where() {
+            " and ("
+        } else {
+            " where ("
+        });
+        for i in 0..keys.len() {
+            qb.push(if i == 0 { "(" } else { " or (" });
+            for (j, (col, desc)) in keys[..=i].iter().enumerate() {
+                let op = match (j == i, desc) {
+                    (false, _) => "=",
+                    (true, false) => ">",
+                    (true, true) => "<",
+                };
+                if j > 0 {
+                    qb.push(" and ");
+                }
+                qb.push(format!(
+                    "{col} {op} (select {col} from usr where user_id = "
+                ));
+                qb.push_bind(after);
+                qb.push(")");
+            }
+            qb.push(")");
+        }
+        qb.push(")");
+        Ok(())
+    }
+}
+
+/// Gets users per the given criteria, returning also the SQL of the query.
+#[tracing::instrument(skip_all)]
+pub async fn do_users_query(
+    pool: &sqlx::PgPool,
+    uq: &UsersQuery,
+) -> anyhow::Result<(String, Vec<User>)> {
+    let keys = uq.order_keys()?;
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("select * from usr");
+    uq.push_where(&mut qb)?;
+    uq.push_after(&mut qb, &keys)?;
+    for (i, (col, desc)) in keys.iter().enumerate() {
+        qb.push(if i == 0 { " order by " } else { ", " });
+        qb.push(format!("{col} {}", if *desc { "desc" } else { "asc" }));
+    }
+    qb.push(" limit ");
+    qb.push_bind(uq.limit as i64);
+    if let Some(offset) = uq.offset {
+        qb.push(" offset ");
+        qb.push_bind(offset as i64);
+    }
 
-pub async fn do_users_query(pool: &sqlx::PgPool, query: &str) -> anyhow::Result<Vec<User>> {
-    let query = unescape_query(query);
-    println!("query: {}\n", query);
+    let query = qb.sql().to_string();
+    tracing::info!("do_users_query: {query}");
+    let users = traced!(qb.build_query_as::<User>(), fetch_all(pool))?;
+    Ok((query, users))
+}
 
-    Ok(sqlx::query_as::<_, User>(&query).fetch_all(pool).await?)
+/// Number of users satisfying the filters, regardless of `after`, `limit`, and `offset`.
+#[tracing::instrument(skip_all)]
+pub async fn count_users(pool: &sqlx::PgPool, uq: &UsersQuery) -> anyhow::Result<i64> {
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("select count(*) from usr");
+    uq.push_where(&mut qb)?;
+    Ok(traced!(qb.build_query_scalar(), fetch_one(pool))?)
 }
 
+#[tracing::instrument(skip_all)]
 pub async fn insert_user(pool: &sqlx::PgPool, req: &UserPostReq) -> anyhow::Result<User> {
-    let record = sqlx::query!(
-        r#"
+    let mut tx = identity::begin(pool).await?;
+    let record = traced!(
+        sqlx::query!(
+            r#"
             insert into usr (email, name) values ($1, $2)
-            returning user_id, email, name, created_at, updated_at
+            returning user_id, email, name, created_at, updated_at, deleted_at
         "#,
-        req.email,
-        req.name,
-    )
-    .fetch_one(pool)
-    .await?;
+            req.email,
+            req.name,
+        ),
+        fetch_one(&mut *tx)
+    )?;
+    tx.commit().await?;
 
     Ok(User {
         user_id: record.user_id,
@@ -27,59 +171,332 @@ pub async fn insert_user(pool: &sqlx::PgPool, req: &UserPostReq) -> anyhow::Resu
         name: record.name.clone(),
         created_at: record.created_at,
         updated_at: record.updated_at,
+        deleted_at: record.deleted_at,
     })
 }
 
-pub async fn update_user(pool: &sqlx::PgPool, req: &UserPutReq) -> anyhow::Result<User> {
-    let record = sqlx::query!(
-        r#"
+/// Deleted users are only reported if `include_deleted` is true.
+#[tracing::instrument(skip_all)]
+pub async fn get_user_by_id(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    include_deleted: bool,
+) -> anyhow::Result<Option<User>> {
+    let user = traced!(
+        sqlx::query_as!(
+            User,
+            "select * from usr where user_id = $1 and ($2 or deleted_at is null)",
+            user_id,
+            include_deleted,
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(user)
+}
+
+/// Deleted users are not reported, as they may share the email with others.
+/// The email is expected normalized, as on write.
+#[tracing::instrument(skip_all)]
+pub async fn get_user_by_email(pool: &sqlx::PgPool, email: &str) -> anyhow::Result<Option<User>> {
+    let user = traced!(
+        sqlx::query_as!(
+            User,
+            "select * from usr where lower(email) = $1 and deleted_at is null",
+            email
+        ),
+        fetch_optional(pool)
+    )?;
+    Ok(user)
+}
+
+/// Conditions on the version of a user (see `User::version`) for an update or delete,
+/// as given by the `If-Match` and `If-None-Match` headers.
+#[derive(Debug, Default)]
+pub struct Preconditions {
+    /// If given, the user must have one of these versions.
+    pub if_match: Option<Vec<DateTime<Utc>>>,
+    /// If given, the user must not have any of these versions.
+    pub if_none_match: Option<Vec<DateTime<Utc>>>,
+}
+
+/// Updates the given fields of the user.
+/// Returns `None` if there is no such user (or it is deleted), or the preconditions are not met.
+#[tracing::instrument(skip_all)]
+pub async fn update_user(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    email: Option<&str>,
+    name: Option<&str>,
+    pre: &Preconditions,
+) -> anyhow::Result<Option<User>> {
+    let mut tx = identity::begin(pool).await?;
+    let record = traced!(
+        sqlx::query!(
+            r#"
             update usr
              set email = coalesce($1, usr.email),
                   name = coalesce($2, usr.name)
             where user_id = $3
-            returning user_id, email, name, created_at, updated_at
+              and deleted_at is null
+              and ($4::timestamptz[] is null or coalesce(updated_at, created_at) = any($4))
+              and ($5::timestamptz[] is null or coalesce(updated_at, created_at) <> all($5))
+            returning user_id, email, name, created_at, updated_at, deleted_at
         "#,
-        req.email,
-        req.name,
-        req.user_id.into(),
-    )
-    .fetch_one(pool)
-    .await?;
+            email,
+            name,
+            user_id,
+            pre.if_match.as_deref(),
+            pre.if_none_match.as_deref(),
+        ),
+        fetch_optional(&mut *tx)
+    )?;
+    tx.commit().await?;
 
-    Ok(User {
+    Ok(record.map(|record| User {
         user_id: record.user_id,
-        email: record.email.clone(),
-        name: record.name.clone(),
+        email: record.email,
+        name: record.name,
         created_at: record.created_at,
         updated_at: record.updated_at,
-    })
+        deleted_at: record.deleted_at,
+    }))
 }
 
+/// Marks the user as deleted.
+/// Returns `None` if there is no such user (or it is already deleted),
+/// or the preconditions are not met.
+#[tracing::instrument(skip_all)]
 pub async fn delete_user_by_id(
     pool: &sqlx::PgPool,
     user_id: &uuid::Uuid,
-) -> anyhow::Result<uuid::Uuid> {
-    let record = sqlx::query!(
-        r#"
-            delete from usr where user_id = $1
+    pre: &Preconditions,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    let mut tx = identity::begin(pool).await?;
+    let record = traced!(
+        sqlx::query!(
+            r#"
+            update usr
+             set deleted_at = now()
+            where user_id = $1
+              and deleted_at is null
+              and ($2::timestamptz[] is null or coalesce(updated_at, created_at) = any($2))
+              and ($3::timestamptz[] is null or coalesce(updated_at, created_at) <> all($3))
+            returning user_id
+        "#,
+            user_id,
+            pre.if_match.as_deref(),
+            pre.if_none_match.as_deref(),
+        ),
+        fetch_optional(&mut *tx)
+    )?;
+    tx.commit().await?;
+    Ok(record.map(|r| r.user_id))
+}
+
+/// Marks the user as deleted.
+/// Returns `None` if there is no such user, or the preconditions are not met.
+#[tracing::instrument(skip_all)]
+pub async fn delete_user_by_email(
+    pool: &sqlx::PgPool,
+    email: &str,
+    pre: &Preconditions,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    let mut tx = identity::begin(pool).await?;
+    let record = traced!(
+        sqlx::query!(
+            r#"
+            update usr
+             set deleted_at = now()
+            where lower(email) = $1
+              and deleted_at is null
+              and ($2::timestamptz[] is null or coalesce(updated_at, created_at) = any($2))
+              and ($3::timestamptz[] is null or coalesce(updated_at, created_at) <> all($3))
+            returning user_id
+        "#,
+            email,
+            pre.if_match.as_deref(),
+            pre.if_none_match.as_deref(),
+        ),
+        fetch_optional(&mut *tx)
+    )?;
+    tx.commit().await?;
+    Ok(record.map(|r| r.user_id))
+}
+
+/// Undoes the deletion of the user.
+/// Returns `None` if there is no such deleted user.
+#[tracing::instrument(skip_all)]
+pub async fn restore_user(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Option<User>> {
+    let mut tx = identity::begin(pool).await?;
+    let user = traced!(
+        sqlx::query_as!(
+            User,
+            r#"
+            update usr
+             set deleted_at = null
+            where user_id = $1
+              and deleted_at is not null
+            returning *
+        "#,
+            user_id,
+        ),
+        fetch_optional(&mut *tx)
+    )?;
+    tx.commit().await?;
+    Ok(user)
+}
+
+/// Permanently removes the given deleted user.
+/// Returns `None` if there is no such deleted user.
+#[tracing::instrument(skip_all)]
+pub async fn purge_user(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+) -> anyhow::Result<Option<uuid::Uuid>> {
+    let mut tx = identity::begin(pool).await?;
+    let record = traced!(
+        sqlx::query!(
+            r#"
+            delete from usr
+            where user_id = $1
+              and deleted_at is not null
             returning user_id
         "#,
-        user_id,
-    )
-    .fetch_one(pool)
-    .await?;
-    Ok(record.user_id)
+            user_id,
+        ),
+        fetch_optional(&mut *tx)
+    )?;
+    tx.commit().await?;
+    Ok(record.map(|r| r.user_id))
 }
 
-pub async fn delete_user_by_email(pool: &sqlx::PgPool, email: &str) -> anyhow::Result<uuid::Uuid> {
-    let record = sqlx::query!(
-        r#"
-            delete from usr where email = $1
+/// Permanently removes the users deleted before the given time, or all deleted users.
+/// Returns the IDs of the removed users.
+#[tracing::instrument(skip_all)]
+pub async fn purge_users(
+    pool: &sqlx::PgPool,
+    deleted_before: Option<DateTime<Utc>>,
+) -> anyhow::Result<Vec<uuid::Uuid>> {
+    let mut tx = identity::begin(pool).await?;
+    let records = traced!(
+        sqlx::query!(
+            r#"
+            delete from usr
+            where deleted_at is not null
+              and ($1::timestamptz is null or deleted_at < $1)
             returning user_id
         "#,
-        email,
-    )
-    .fetch_one(pool)
-    .await?;
-    Ok(record.user_id)
+            deleted_before,
+        ),
+        fetch_all(&mut *tx)
+    )?;
+    tx.commit().await?;
+    Ok(records.into_iter().map(|r| r.user_id).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::BadInput;
+
+    async fn pool() -> sqlx::PgPool {
+        dotenvy::dotenv().ok();
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL for the tests");
+        sqlx::PgPool::connect(&url).await.unwrap()
+    }
+
+    /// Inserts users with the same name, returning the filter on their emails, and their IDs.
+    async fn insert_users(pool: &sqlx::PgPool, count: usize) -> (Filter, Vec<uuid::Uuid>) {
+        let domain = format!("{}.cursor.test", uuid::Uuid::new_v4());
+        let mut ids = vec![];
+        for i in 0..count {
+            let req = UserPostReq {
+                email: format!("user{i}@{domain}"),
+                name: "Cursor Test".to_string(),
+            };
+            ids.push(insert_user(pool, &req).await.unwrap().user_id);
+        }
+        let filter = Filter::parse_param("email", &format!("like.*@{domain}")).unwrap();
+        (filter, ids)
+    }
+
+    /// The IDs of the users in each page, following the `after` cursor from one to the next.
+    async fn pages(pool: &sqlx::PgPool, mut uq: UsersQuery) -> Vec<Vec<uuid::Uuid>> {
+        let mut pages = vec![];
+        loop {
+            let (_, users) = do_users_query(pool, &uq).await.unwrap();
+            let ids: Vec<_> = users.iter().map(|u| u.user_id).collect();
+            let full = ids.len() == uq.limit as usize;
+            uq.after = ids.last().copied();
+            pages.push(ids);
+            if !full {
+                return pages;
+            }
+        }
+    }
+
+    #[test]
+    fn after_compares_each_key_after_the_equal_previous_ones() {
+        let uq = UsersQuery {
+            after: Some(uuid::Uuid::nil()),
+            include_deleted: true,
+            ..Default::default()
+        };
+        let mut qb = QueryBuilder::new("select * from usr");
+        uq.push_after(&mut qb, &[("name", true), ("user_id", false)])
+            .unwrap();
+        assert_eq!(
+            qb.sql(),
+            "select * from usr where ((name < (select name from usr where user_id = $1)) \
+             or (name = (select name from usr where user_id = $2) \
+             and user_id > (select user_id from usr where user_id = $3)))"
+        );
+    }
+
+    #[tokio::test]
+    async fn after_pages_through_ties_on_the_sort_key() {
+        let pool = pool().await;
+        let (filter, mut ids) = insert_users(&pool, 5).await;
+        ids.sort();
+        for desc in [false, true] {
+            let uq = UsersQuery {
+                filters: vec![filter.clone()],
+                order_by: vec![("name".to_string(), desc)],
+                limit: 2,
+                ..Default::default()
+            };
+            let pages = pages(&pool, uq).await;
+            // all tied on the name, so ordered by the user ID
+            assert_eq!(pages.len(), 3);
+            assert_eq!(pages.concat(), ids);
+        }
+    }
+
+    #[tokio::test]
+    async fn after_an_unknown_user_is_empty() {
+        let pool = pool().await;
+        let (filter, _) = insert_users(&pool, 2).await;
+        let uq = UsersQuery {
+            filters: vec![filter],
+            limit: 5,
+            after: Some(uuid::Uuid::new_v4()),
+            ..Default::default()
+        };
+        let (_, users) = do_users_query(&pool, &uq).await.unwrap();
+        assert!(users.is_empty());
+    }
+
+    #[test]
+    fn after_is_rejected_when_ordering_by_a_nullable_column() {
+        let uq = UsersQuery {
+            order_by: vec![("updated_at".to_string(), false)],
+            after: Some(uuid::Uuid::nil()),
+            ..Default::default()
+        };
+        let e = uq.order_keys().unwrap_err();
+        assert!(e.is::<BadInput>(), "{e}");
+    }
 }
@@ -7,6 +7,7 @@ mod config;
 mod db;
 mod models;
 mod server;
+mod telemetry;
 
 #[derive(clap::Parser, Debug)]
 #[clap(
@@ -33,24 +34,29 @@ async fn main() {
         Opts::Db(opts) => dispatch(&opts).await,
         Opts::Health => health(),
     };
+    telemetry::shutdown();
 }
 
 fn init() {
     dotenvy::dotenv().ok();
-    env_logger::init();
+    let res = config::TelemetryConfig::get().and_then(|config| telemetry::init(&config));
+    if let Err(e) = res {
+        eprintln!("Error initializing logging: {e}");
+        std::process::exit(1);
+    }
 }
 
 async fn serve(opts: &ServeOpts) {
     match server::launch(opts).await {
         Ok(_) => (),
-        Err(e) => log::error!("Error launching server: {}", e),
+        Err(e) => tracing::error!("Error launching server: {}", e),
     }
 }
 
 async fn dispatch(opts: &DbOpts) {
     match db::dispatch::dispatch(opts).await {
         Ok(_) => (),
-        Err(e) => log::error!("Error dispatching db command: {}", e),
+        Err(e) => tracing::error!("Error dispatching db command: {}", e),
     }
 }
 
@@ -59,7 +65,7 @@ fn health() {
     match serde_json::to_string_pretty(&status) {
         Ok(json) => println!("{}", json),
         Err(e) => {
-            log::error!("Error serializing health status: {}", e);
+            tracing::error!("Error serializing health status: {}", e);
             println!("{:?}", status);
         }
     }
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 #[derive(sqlx::FromRow, Deserialize, Serialize, Debug)]
 pub struct User {
@@ -8,4 +9,111 @@ pub struct User {
     pub name: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
+    /// Set when the user is (soft) deleted.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// Columns of the `usr` table with their SQL types and nullability.
+    pub const COLUMNS: &'static [(&'static str, &'static str, bool)] = &[
+        ("user_id", "uuid", false),
+        ("email", "text", false),
+        ("name", "text", false),
+        ("created_at", "timestamptz", false),
+        ("updated_at", "timestamptz", true),
+        ("deleted_at", "timestamptz", true),
+    ];
+
+    pub fn column_type(name: &str) -> Option<&'static str> {
+        Self::COLUMNS
+            .iter()
+            .find(|(col, _, _)| *col == name)
+            .map(|(_, typ, _)| *typ)
+    }
+
+    /// Changes whenever the user is modified: `updated_at`, or `created_at` if never updated.
+    pub fn version(&self) -> DateTime<Utc> {
+        self.updated_at.unwrap_or(self.created_at)
+    }
+
+    /// Entity tag for the current version of the user, e.g., `"1708300800123456"`.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version().timestamp_micros())
+    }
+
+    pub fn is_nullable(name: &str) -> bool {
+        Self::COLUMNS
+            .iter()
+            .any(|(col, _, nullable)| *col == name && *nullable)
+    }
+}
+
+/// What a principal (the authenticated client) may do,
+/// granted directly to API keys (as scopes) and JWTs, or via roles.
+#[derive(
+    clap::ValueEnum, Deserialize, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq, Hash,
+)]
+pub enum Permission {
+    /// Generic queries, query history, schema, and tables
+    #[value(name = "query")]
+    #[serde(rename = "query")]
+    Query,
+    /// Reading users, roles, groups, and sessions
+    #[value(name = "users:read")]
+    #[serde(rename = "users:read")]
+    UsersRead,
+    /// Modifying users, group members, passwords, and sessions
+    #[value(name = "users:write")]
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    /// Modifying roles and their assignments, and purging users
+    #[value(name = "admin")]
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Permission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::Query => "query",
+            Permission::UsersRead => "users:read",
+            Permission::UsersWrite => "users:write",
+            Permission::Admin => "admin",
+        }
+    }
+}
+
+/// A role that can be assigned to users, e.g., `operator` or `viewer`.
+#[derive(sqlx::FromRow, Deserialize, Serialize, ToSchema, Debug)]
+pub struct Role {
+    #[schema(value_type = str)]
+    pub role_id: uuid::Uuid,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Permissions of the principals with this role, e.g., `users:read`.
+    pub permissions: Vec<String>,
+    /// Postgres role for the generic queries of the principals with this role.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub db_role: Option<String>,
+    #[schema(value_type = str)]
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<str>)]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A group of users, e.g., a cruise team.
+#[derive(sqlx::FromRow, Deserialize, Serialize, ToSchema, Debug)]
+pub struct Group {
+    #[schema(value_type = str)]
+    pub group_id: uuid::Uuid,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[schema(value_type = str)]
+    pub created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<str>)]
+    pub updated_at: Option<DateTime<Utc>>,
 }
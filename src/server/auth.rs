@@ -0,0 +1,210 @@
+//! Authentication of the API clients, with keys given in the `X-API-Key` header,
+//! or bearer tokens (JWTs, see [`crate::server::jwt`], or login sessions),
+//! and authorization based on the permissions of their roles.
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::{self, FromFnLayer, Next};
+use axum::response::{IntoResponse, Response};
+use futures::future::BoxFuture;
+use utoipa::openapi::security::{
+    ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityRequirement, SecurityScheme,
+};
+
+use crate::db::identity::{self, Identity};
+use crate::db::{api_keys, roles, sessions};
+use crate::models::Permission;
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::sessions::bearer_token;
+use crate::server::AppState;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The authenticated client, available to the handlers as an extractor
+/// (use `Option<Principal>` where authentication is not required).
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// Identifies the client: `api-key:<name>`, the `sub` of a JWT,
+    /// or the ID of the user of a login session.
+    pub subject: String,
+    /// Names of the roles of the principal.
+    pub roles: Vec<String>,
+    /// Permissions granted directly or by the roles.
+    pub permissions: Vec<String>,
+    /// Postgres role for the generic queries, from the first of the roles (by name) having one.
+    pub db_role: Option<String>,
+}
+
+impl Principal {
+    pub fn new(subject: String, roles: Vec<String>, permissions: Vec<String>) -> Self {
+        Principal {
+            subject,
+            roles,
+            permissions,
+            db_role: None,
+        }
+    }
+
+    /// The identity given to Postgres for the statements of the request.
+    pub fn identity(&self) -> Identity {
+        Identity {
+            user_id: self.subject.clone(),
+            roles: self.roles.clone(),
+        }
+    }
+
+    /// Whether the principal is the given user, as with a login session.
+    pub fn is_user(&self, user_id: &uuid::Uuid) -> bool {
+        self.subject == user_id.to_string()
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions.iter().any(|p| p == permission.as_str())
+    }
+
+    /// Adds the permissions and database role of the principal's roles.
+    async fn resolve_roles(mut self, pool: &sqlx::PgPool) -> anyhow::Result<Self> {
+        if self.roles.is_empty() {
+            return Ok(self);
+        }
+        for role in roles::get_roles_by_name(pool, &self.roles).await? {
+            self.permissions.extend(role.permissions);
+            self.db_role = self.db_role.or(role.db_role);
+        }
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Not authenticated"))
+    }
+}
+
+/// Whether the route is available without authentication.
+/// The path is relative to `/api`.
+fn is_public(path: &str) -> bool {
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    matches!(first, "ping" | "health" | "login" | "logout" | "session")
+}
+
+/// Middleware requiring an authenticated client, except for the public routes.
+/// The client gives an API key in `X-API-Key`, or a bearer token:
+/// a JWT (if enabled), or the token of a login session.
+/// What the client may do is then checked by each route (see [`require`]).
+pub async fn authenticate(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    if is_public(req.uri().path()) {
+        return next.run(req).await;
+    }
+    let principal = match principal(&state, req.headers()).await {
+        Ok(principal) => principal,
+        Err(e) => return e.into_response(),
+    };
+    tracing::debug!("authenticate: {principal:?}");
+    tracing::Span::current().record("principal", &principal.subject);
+    let identity = principal.identity();
+    req.extensions_mut().insert(principal.clone());
+    let mut res = identity::scope(identity, next.run(req)).await;
+    // for the access log
+    res.extensions_mut().insert(principal);
+    res
+}
+
+async fn principal(state: &AppState, headers: &HeaderMap) -> ApiResult<Principal> {
+    let unauthorized = |detail: String| ApiError::new(StatusCode::UNAUTHORIZED, detail);
+    let principal = if let Some(key) = headers.get(API_KEY_HEADER) {
+        let key = key.to_str().unwrap_or_default();
+        let api_key = api_keys::authenticate(&state.pool, key)
+            .await?
+            .ok_or_else(|| unauthorized("Invalid API key".to_string()))?;
+        let subject = format!("api-key:{}", api_key.name);
+        Principal::new(subject, api_key.roles, api_key.scopes)
+    } else if let Some(token) = bearer_token(headers) {
+        match &state.jwt {
+            // a JWT has three dot-separated parts; a session token is hexadecimal
+            Some(jwt) if token.contains('.') => jwt
+                .validate(token)
+                .map_err(|e| unauthorized(format!("Invalid token: {e}")))?,
+            _ => {
+                let session = sessions::get_session_by_token(&state.pool, token)
+                    .await?
+                    .ok_or_else(|| unauthorized("Invalid token".to_string()))?;
+                let roles = roles::get_user_roles(&state.pool, &session.user_id).await?;
+                let roles = roles.into_iter().map(|r| r.name).collect();
+                Principal::new(session.user_id.to_string(), roles, vec![])
+            }
+        }
+    } else {
+        return Err(unauthorized("Missing credentials".to_string()));
+    };
+    Ok(principal.resolve_roles(&state.pool).await?)
+}
+
+type AuthorizeFn = fn(State<Permission>, Request, Next) -> BoxFuture<'static, Response>;
+
+/// Layer declaring the permission required by a route, to be added with `route_layer`.
+/// Without a principal, that is, if authentication is not enabled, the request is let through.
+pub fn require(
+    permission: Permission,
+) -> FromFnLayer<AuthorizeFn, Permission, (State<Permission>, Request)> {
+    middleware::from_fn_with_state(permission, authorize as AuthorizeFn)
+}
+
+fn authorize(
+    State(permission): State<Permission>,
+    req: Request,
+    next: Next,
+) -> BoxFuture<'static, Response> {
+    Box::pin(async move {
+        let principal = req.extensions().get::<Principal>();
+        if let Some(principal) = principal.filter(|p| !p.has_permission(permission)) {
+            tracing::info!(
+                "authorize: {} lacks '{}'",
+                principal.subject,
+                permission.as_str()
+            );
+            let detail = format!("Missing the '{}' permission", permission.as_str());
+            return ApiError::new(StatusCode::FORBIDDEN, detail).into_response();
+        }
+        next.run(req).await
+    })
+}
+
+/// Declares the security schemes, either of which is required by all operations.
+pub struct SecurityAddon;
+
+impl utoipa::Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                API_KEY_HEADER,
+                "Only required if authentication is enabled (`SQLXUM_REQUIRE_AUTH=true`)",
+            ))),
+        );
+        components.add_security_scheme(
+            "bearer_jwt",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Only accepted if JWT keys are configured"))
+                    .build(),
+            ),
+        );
+        openapi.security = Some(vec![
+            SecurityRequirement::new("api_key", Vec::<String>::new()),
+            SecurityRequirement::new("bearer_jwt", Vec::<String>::new()),
+        ]);
+    }
+}
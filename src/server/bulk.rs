@@ -0,0 +1,110 @@
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{
+    extract::{Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::db::bulk::{self, Format};
+use crate::models::Permission::{UsersRead, UsersWrite};
+use crate::server::auth::require;
+use crate::server::error::ApiResult;
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/users/import",
+            post(import_users).route_layer(require(UsersWrite)),
+        )
+        .route(
+            "/users/export",
+            get(export_users).route_layer(require(UsersRead)),
+        )
+        .with_state(app_state)
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParams {
+    /// Format of the request body. By default, according to the `Content-Type`, or JSON.
+    #[param(inline)]
+    format: Option<Format>,
+
+    /// Insert the valid users even if others fail, reporting the failures.
+    #[serde(default)]
+    continue_on_error: bool,
+}
+
+/// Import users from a JSON array, NDJSON, or CSV.
+///
+/// Each user is validated as in `POST /users`, and all of them are inserted in a single
+/// transaction. By default, nothing is inserted if any user fails, in which case the status
+/// is 422 (invalid users) or as with `POST /users` (e.g., 409 for a duplicate email).
+/// With `continue_on_error=true`, the valid users are inserted and the failures reported.
+#[utoipa::path(
+    post,
+    path = "/users/import",
+    params(ImportParams),
+    request_body(content = String, description = "The users, e.g., as CSV with an `email,name` header",
+        content_type = "text/csv"),
+    responses(
+       (status = 200, description = "Import report", body = crate::db::bulk::ImportReport),
+       (status = 422, description = "Invalid users, none inserted", body = crate::db::bulk::ImportReport)
+    )
+)]
+pub async fn import_users(
+    state: State<AppState>,
+    Query(params): Query<ImportParams>,
+    headers: HeaderMap,
+    body: String,
+) -> ApiResult<Response> {
+    let format = params
+        .format
+        .or_else(|| {
+            let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+            Format::from_content_type(content_type)
+        })
+        .unwrap_or_default();
+    tracing::info!("import_users: format={format:?} {params:?}");
+    let rows = bulk::parse_users(format, &body)?;
+    let report = bulk::import_users(&state.pool, rows, params.continue_on_error).await?;
+    let status = if report.inserted == 0 && !report.errors.is_empty() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(report)).into_response())
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// By default, JSON.
+    #[param(inline)]
+    format: Option<Format>,
+}
+
+/// Export all users, oldest first, as a JSON array, NDJSON, or CSV.
+#[utoipa::path(
+    get,
+    path = "/users/export",
+    params(ExportParams),
+    responses(
+       (status = 200, description = "The users", body = String,
+        content_type = ["application/json", "application/x-ndjson", "text/csv"])
+    )
+)]
+pub async fn export_users(
+    state: State<AppState>,
+    Query(params): Query<ExportParams>,
+) -> ApiResult<Response> {
+    let format = params.format.unwrap_or_default();
+    tracing::info!("export_users: format={format:?}");
+    let data = bulk::export_users(&state.pool, format).await?;
+    let content_type = [(header::CONTENT_TYPE, format.content_type())];
+    Ok((content_type, data).into_response())
+}
@@ -0,0 +1,97 @@
+use lru::LruCache;
+use serde_json::Value;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::common::unescape_query;
+use crate::config::CacheConfig;
+
+/// In-memory LRU cache of generic query results, keyed by the (trimmed)
+/// query text and parameters.
+pub struct QueryCache {
+    entries: Mutex<LruCache<String, Entry>>,
+    ttl: Duration,
+    max_entry_bytes: usize,
+}
+
+struct Entry {
+    value: Value,
+    inserted: Instant,
+}
+
+impl QueryCache {
+    /// Creates the cache, or `None` if disabled by the configuration.
+    pub fn new(config: &CacheConfig) -> Option<Self> {
+        let capacity = NonZeroUsize::new(config.capacity)?;
+        Some(QueryCache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl: config.ttl,
+            max_entry_bytes: config.max_entry_bytes,
+        })
+    }
+
+    /// The key for a query, also distinguished by the `caller`, as results may depend on it
+    /// (e.g., on the database role the query is run as, or row-level security policies).
+    /// The query is only trimmed, as any other whitespace may be within a string literal.
+    pub fn key(query: &str, params: &[Value], caller: &str) -> String {
+        let query = unescape_query(query);
+        format!("{caller}\n{}\n{}", query.trim(), Value::from(params))
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.inserted.elapsed() < self.ttl => Some(entry.value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Caches the value unless it exceeds the size limit.
+    pub fn put(&self, key: String, value: &Value) {
+        let size = value.to_string().len();
+        if size > self.max_entry_bytes {
+            tracing::debug!("Not caching result of {size} bytes");
+            return;
+        }
+        let entry = Entry {
+            value: value.clone(),
+            inserted: Instant::now(),
+        };
+        self.entries.lock().unwrap().put(key, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn key_ignores_surrounding_whitespace() {
+        assert_eq!(
+            QueryCache::key("  select 1\n", &[], ""),
+            QueryCache::key("select 1", &[], "")
+        );
+    }
+
+    #[test]
+    fn key_keeps_whitespace_within_the_query() {
+        assert_ne!(
+            QueryCache::key("select 'a  b'", &[], ""),
+            QueryCache::key("select 'a b'", &[], "")
+        );
+    }
+
+    #[test]
+    fn key_depends_on_params_and_caller() {
+        let key = QueryCache::key("select $1", &[json!(1)], "a");
+        assert_ne!(key, QueryCache::key("select $1", &[json!(2)], "a"));
+        assert_ne!(key, QueryCache::key("select $1", &[json!(1)], "b"));
+    }
+}
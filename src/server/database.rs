@@ -1,30 +1,86 @@
+use std::net::SocketAddr;
 use std::time::Instant;
 
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
 use axum::{
-    extract::{Query, State},
-    routing::{get, post},
+    extract::{ConnectInfo, Path, Query, State},
+    routing::{get, patch, post, put},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
 
+use crate::db::filter::{parse_order, Filter};
 use crate::db::generic;
+use crate::db::history::QueryRecord;
+use crate::db::row_history::{self, RowChange};
 use crate::db::users;
+use crate::models::Permission;
 use crate::models::User;
+use crate::server::auth::{require, Principal};
+use crate::server::cache::QueryCache;
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::etag;
+use crate::server::limits::limit_queries;
+use crate::server::validation::{normalize_str, Normalize, ValidatedJson};
 use crate::server::AppState;
 
+#[allow(deprecated)] // for the PUT and DELETE aliases on /users
 pub async fn create_router(app_state: AppState) -> anyhow::Result<Router> {
     Ok(Router::new()
-        .route("/query", post(do_query))
+        .route(
+            "/query",
+            post(do_query)
+                .route_layer(middleware::from_fn_with_state(
+                    app_state.clone(),
+                    limit_queries,
+                ))
+                .route_layer(require(Permission::Query)),
+        )
         .route(
             "/users",
             get(get_users)
-                .put(update_user)
-                .post(add_user)
-                .delete(delete_user),
+                .route_layer(require(Permission::UsersRead))
+                .merge(
+                    put(update_user)
+                        .post(add_user)
+                        .delete(delete_user)
+                        .route_layer(require(Permission::UsersWrite)),
+                ),
+        )
+        .route(
+            "/users/:user_id",
+            get(get_user)
+                .route_layer(require(Permission::UsersRead))
+                .merge(
+                    patch(patch_user)
+                        .delete(delete_user_by_id)
+                        .route_layer(require(Permission::UsersWrite)),
+                ),
+        )
+        .route(
+            "/users/by-email/:email",
+            get(get_user_by_email).route_layer(require(Permission::UsersRead)),
+        )
+        .route(
+            "/users/:user_id/history",
+            get(get_user_history).route_layer(require(Permission::UsersRead)),
+        )
+        .route(
+            "/users/:user_id/restore",
+            post(restore_user).route_layer(require(Permission::UsersWrite)),
+        )
+        .route(
+            "/users/:user_id/purge",
+            post(purge_user).route_layer(require(Permission::Admin)),
+        )
+        .route(
+            "/users/purge",
+            post(purge_users).route_layer(require(Permission::Admin)),
         )
         .with_state(app_state))
 }
@@ -33,50 +89,127 @@ pub async fn create_router(app_state: AppState) -> anyhow::Result<Router> {
 pub struct QueryReq {
     /// The query to execute.
     query: String,
+
+    /// Values for the `$1`, `$2`, ... placeholders in the query, if any.
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    params: Vec<Value>,
+
+    /// Run the query in a read-only transaction.
+    /// Only read-only queries are eligible for caching.
+    #[serde(default)]
+    read_only: bool,
 }
 
 /// Perform a database query.
+///
+/// If the result cache is enabled, read-only queries are served from it
+/// unless the request includes `Cache-Control: no-cache`.
+/// The `X-Cache` response header indicates `HIT`, `MISS`, or `BYPASS`.
 #[utoipa::path(
     post,
     path = "/query",
     request_body = QueryReq,
     responses(
-       (status = 200, description = "Query response", body = Value)
+       (status = 200, description = "Query response", body = Value,
+        headers(("x-cache" = String, description = "HIT, MISS, or BYPASS, when the cache is enabled")))
     )
 )]
-pub async fn do_query(state: State<AppState>, Json(req): Json<QueryReq>) -> Json<Value> {
-    log::debug!("do_query = {req:?}");
-    let pool = &state.pool;
+pub async fn do_query(
+    state: State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    principal: Option<Principal>,
+    headers: HeaderMap,
+    Json(req): Json<QueryReq>,
+) -> ApiResult<Response> {
+    tracing::debug!("do_query = {req:?}");
     let query = req.query;
-    match generic::do_query(pool, &query).await {
-        Ok(res) => Json(res),
-        Err(e) => {
-            log::error!("Error: {:?}", e);
-            Json(json!({
-                "query": query,
-                "error": e.to_string()
-            }))
+
+    let cache = state.cache.as_deref().filter(|_| req.read_only);
+    let bypass = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("no-cache"));
+    let db_role = principal.as_ref().and_then(|p| p.db_role.as_deref());
+    let cache_caller = match &principal {
+        Some(p) => format!("{:?} {} {:?}", p.db_role, p.subject, p.roles),
+        None => String::new(),
+    };
+    let key = QueryCache::key(&query, &req.params, &cache_caller);
+    if let Some(cache) = cache.filter(|_| !bypass) {
+        if let Some(res) = cache.get(&key) {
+            tracing::debug!("do_query: cache hit");
+            return Ok(([("x-cache", "HIT")], Json(res)).into_response());
         }
     }
+
+    let pool = state.pool_for(db_role)?;
+    let start = Instant::now();
+    let res = generic::do_query(pool, &query, &req.params, req.read_only, db_role).await;
+    let caller = match principal {
+        Some(principal) => Some(principal.subject),
+        None => Some(addr.ip().to_string()),
+    };
+    let rec = QueryRecord::new(&query, &req.params, caller, start.elapsed(), &res);
+    state.history.record(&rec).await;
+    let res = res?;
+    Ok(match cache {
+        Some(cache) => {
+            cache.put(key, &res);
+            let x_cache = if bypass { "BYPASS" } else { "MISS" };
+            ([("x-cache", x_cache)], Json(res)).into_response()
+        }
+        None if state.cache.is_some() => ([("x-cache", "BYPASS")], Json(res)).into_response(),
+        None => Json(res).into_response(),
+    })
 }
 
 #[derive(Deserialize, Debug, IntoParams)]
 pub struct QueryParams {
-    /// Where clause. Example: `name = 'Foo'`.
+    /// Raw where clause. Example: `name = 'Foo'`.
+    /// Only accepted if the server was launched with `--unsafe-where`.
     r#where: Option<String>,
 
-    /// Limit the number of results. By default, some small number.
+    /// Limit the number of results. By default, 5.
     limit: Option<u32>,
+
+    /// Comma-separated columns to order by, each optionally followed by `.asc` or `.desc`.
+    /// Example: `name.desc,created_at`. By default, `created_at`.
+    /// `user_id` is always used as the last key.
+    order_by: Option<String>,
+
+    /// Number of users to skip.
+    offset: Option<u32>,
+
+    /// Only users after the one with this ID, according to the order.
+    /// Typically, the `next` value from the previous page.
+    /// Not supported when ordering by a nullable column.
+    #[param(value_type = Option<String>)]
+    after: Option<uuid::Uuid>,
+
+    /// Also include deleted users.
+    #[serde(default)]
+    include_deleted: bool,
 }
 
 impl QueryParams {
-    fn adjust_query(self, query: &str) -> String {
-        let mut query = query.to_string();
-        if let Some(cond) = &self.r#where {
-            query = format!("{query} where {cond}");
-        }
-        let limit = self.limit.unwrap_or(5);
-        format!("{query} limit {limit}")
+    /// Names of the parameters above, so they are not taken as filters.
+    const NAMES: &'static [&'static str] = &[
+        "where",
+        "limit",
+        "order_by",
+        "offset",
+        "after",
+        "include_deleted",
+    ];
+
+    /// Structured filters given by all the other parameters.
+    fn filters(pairs: &[(String, String)]) -> anyhow::Result<Vec<Filter>> {
+        pairs
+            .iter()
+            .filter(|(key, _)| !Self::NAMES.contains(&key.as_str()))
+            .map(|(key, value)| Filter::parse_param(key, value))
+            .collect()
     }
 }
 
@@ -89,6 +222,9 @@ pub struct UserRes {
     pub created_at: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub updated_at: Option<String>,
+    /// Only present for deleted users.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
 }
 
 impl UserRes {
@@ -99,111 +235,191 @@ impl UserRes {
             name: user.name.clone(),
             created_at: user.created_at.to_string(),
             updated_at: user.updated_at.map(|d| d.to_string()),
+            deleted_at: user.deleted_at.map(|d| d.to_string()),
         }
     }
 }
 
 /// Get users
+///
+/// Users can be filtered with `<column>=[not.]<op>.<value>`, where `<op>` is one of
+/// `eq`, `neq`, `gt`, `gte`, `lt`, `lte`, `like`, `ilike` (with `*` as wildcard),
+/// `in` (e.g., `in.(a,b)`), or `is` (`null`, `true`, `false`).
+/// Multiple filters are combined with `and`; conditions can also be grouped,
+/// e.g., `or=(name.eq.Foo,and(email.like.*@example.net,created_at.gt.2024-02-18))`.
 #[utoipa::path(
     get,
     path = "/users",
     params(QueryParams),
     responses(
-       (status = 200, description = "List of users", body = Vec<UserRes>)
+       (status = 200, description = "List of users", body = Vec<UserRes>,
+        headers(("x-total-count" = i64, description = "Number of users satisfying the filters")))
     )
 )]
 pub async fn get_users(
     state: State<AppState>,
     Query(params): Query<QueryParams>,
-) -> impl IntoResponse {
-    log::info!("get_users: {params:?}");
-    let query = params.adjust_query("select * from usr");
+    Query(pairs): Query<Vec<(String, String)>>,
+) -> ApiResult<Response> {
+    tracing::info!("get_users: {pairs:?}");
+    if params.r#where.is_some() && !state.unsafe_where {
+        return Err(ApiError::bad_request(
+            "The 'where' parameter is disabled; use structured filters instead",
+        ));
+    }
+    let filters = QueryParams::filters(&pairs)?;
+    let uq = users::UsersQuery {
+        filters,
+        raw_where: params.r#where,
+        order_by: params
+            .order_by
+            .as_deref()
+            .map(parse_order)
+            .unwrap_or_default(),
+        limit: params.limit.unwrap_or(5),
+        offset: params.offset,
+        after: params.after,
+        include_deleted: params.include_deleted,
+    };
 
     let pool = &state.pool;
     let start = Instant::now();
-    match users::do_users_query(pool, &query).await {
-        Ok(res) => {
-            let elapsed = format!("{:?}", start.elapsed());
-            let result: Vec<UserRes> = res.iter().map(UserRes::from_user).collect();
-            Json(json!({
-                "query": query,
-                "result": result,
-                "elapsed": elapsed,
-            }))
-            .into_response()
-        }
-        Err(e) => {
-            log::error!("Error: {:?}", e);
-            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
-        }
-    }
+    let (query, res) = users::do_users_query(pool, &uq).await?;
+    let count = users::count_users(pool, &uq).await?;
+    let elapsed = format!("{:?}", start.elapsed());
+    let result: Vec<UserRes> = res.iter().map(UserRes::from_user).collect();
+    // only when the page is full, as otherwise there are no more users:
+    let next = match res.last() {
+        Some(user) if res.len() == uq.limit as usize => Some(user.user_id),
+        _ => None,
+    };
+    let headers = [("x-total-count", count.to_string())];
+    let body = Json(json!({
+        "query": query,
+        "result": result,
+        "next": next,
+        "elapsed": elapsed,
+    }));
+    Ok((headers, body).into_response())
 }
 
+/// Maximum length of an email address, per RFC 5321.
+const EMAIL_MAX_LEN: u64 = 254;
+const NAME_MAX_LEN: u64 = 100;
+
 /// Request content to add a user
-#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
 pub struct UserPostReq {
-    /// Email of the user
+    /// Email of the user. Surrounding whitespace is removed, and it is converted to lowercase.
+    #[schema(format = "email", max_length = 254)]
+    #[validate(
+        email(message = "must be a valid email address"),
+        length(max = "EMAIL_MAX_LEN", message = "must be at most 254 characters")
+    )]
     pub email: String,
-    /// Name of the user
+    /// Name of the user. Surrounding whitespace is removed.
+    #[schema(min_length = 1, max_length = 100)]
+    #[validate(length(
+        min = 1,
+        max = "NAME_MAX_LEN",
+        message = "must have between 1 and 100 characters"
+    ))]
     pub name: String,
 }
 
+impl Normalize for UserPostReq {
+    fn normalize(&mut self) {
+        normalize_str(&mut self.email, true);
+        normalize_str(&mut self.name, false);
+    }
+}
+
 /// Add a user
 #[utoipa::path(
     post,
     path = "/users",
     request_body = UserPostReq,
     responses(
-       (status = 200, description = "User registered", body = UserRes)
+       (status = 200, description = "User registered", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 409, description = "Email already registered", body = crate::server::error::ProblemDetails)
     )
 )]
-pub async fn add_user(state: State<AppState>, Json(req): Json<UserPostReq>) -> impl IntoResponse {
-    log::info!("add_user: {req:?}");
+pub async fn add_user(
+    state: State<AppState>,
+    ValidatedJson(req): ValidatedJson<UserPostReq>,
+) -> ApiResult<Response> {
+    tracing::info!("add_user: {req:?}");
     let pool = &state.pool;
-    match users::insert_user(pool, &req).await {
-        Ok(user) => {
-            let user_res = UserRes::from_user(&user);
-            Json(user_res).into_response()
-        }
-        Err(e) => {
-            log::error!("Error: {:?}", e);
-            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
-        }
-    }
+    let user = users::insert_user(pool, &req).await?;
+    Ok(user_response(&user))
 }
 
 /// Request content to update a user
-#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
 pub struct UserPutReq {
     /// ID of the user
     #[schema(value_type = str)]
     pub user_id: uuid::Uuid,
     /// New email of the user
+    #[schema(format = "email", max_length = 254)]
+    #[validate(
+        email(message = "must be a valid email address"),
+        length(max = "EMAIL_MAX_LEN", message = "must be at most 254 characters")
+    )]
     pub email: Option<String>,
     /// New name of the user
+    #[schema(min_length = 1, max_length = 100)]
+    #[validate(length(
+        min = 1,
+        max = "NAME_MAX_LEN",
+        message = "must have between 1 and 100 characters"
+    ))]
     pub name: String,
 }
 
+impl Normalize for UserPutReq {
+    fn normalize(&mut self) {
+        if let Some(email) = &mut self.email {
+            normalize_str(email, true);
+        }
+        normalize_str(&mut self.name, false);
+    }
+}
+
 /// Update a user
+///
+/// Deprecated: use `PATCH /users/{user_id}` instead.
+#[deprecated]
 #[utoipa::path(
     put,
     path = "/users",
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only if the user has one of these ETags"),
+        ("If-None-Match" = Option<String>, Header, description = "Only if the user has none of these ETags"),
+    ),
     request_body = UserPutReq,
     responses(
-       (status = 200, description = "User registered", body = UserRes)
+       (status = 200, description = "User updated", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails),
+       (status = 412, description = "User modified since the given ETag", body = crate::server::error::ProblemDetails)
     )
 )]
-pub async fn update_user(state: State<AppState>, Json(req): Json<UserPutReq>) -> impl IntoResponse {
-    log::info!("update_user: {req:?}");
+pub async fn update_user(
+    state: State<AppState>,
+    headers: HeaderMap,
+    ValidatedJson(req): ValidatedJson<UserPutReq>,
+) -> ApiResult<Response> {
+    tracing::info!("update_user: {req:?}");
     let pool = &state.pool;
-    match users::update_user(pool, &req).await {
-        Ok(user) => {
-            let user_res = UserRes::from_user(&user);
-            Json(user_res).into_response()
-        }
-        Err(e) => {
-            log::error!("Error: {:?}", e);
-            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+    let pre = etag::preconditions(&headers);
+    let user_id = &req.user_id;
+    match users::update_user(pool, user_id, req.email.as_deref(), Some(&req.name), &pre).await? {
+        Some(user) => Ok(user_response(&user)),
+        None => {
+            let current = users::get_user_by_id(pool, user_id, false).await;
+            Err(no_user_error(current, &user_id.to_string()))
         }
     }
 }
@@ -226,39 +442,384 @@ pub struct UserDeleteRes {
 }
 
 /// Delete a user
+///
+/// Deprecated: use `DELETE /users/{user_id}` instead.
+#[deprecated]
 #[utoipa::path(
     delete,
     path = "/users",
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only if the user has one of these ETags"),
+        ("If-None-Match" = Option<String>, Header, description = "Only if the user has none of these ETags"),
+    ),
     request_body = UserDeleteReq,
     responses(
-       (status = 200, description = "User deleted", body = UserDeleteRes)
+       (status = 200, description = "User deleted", body = UserDeleteRes),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails),
+       (status = 412, description = "User modified since the given ETag", body = crate::server::error::ProblemDetails)
     )
 )]
 pub async fn delete_user(
     state: State<AppState>,
+    headers: HeaderMap,
     Json(req): Json<UserDeleteReq>,
-) -> impl IntoResponse {
-    log::info!("delete_user: {req:?}");
+) -> ApiResult<Json<UserDeleteRes>> {
+    tracing::info!("delete_user: {req:?}");
     if req.user_id.is_none() && req.email.is_none() {
-        return (
-            StatusCode::BAD_REQUEST,
+        return Err(ApiError::bad_request(
             "Either user_id or email must be provided",
-        )
-            .into_response();
+        ));
     }
 
     let pool = &state.pool;
+    let pre = etag::preconditions(&headers);
 
-    let res = if let Some(user_id) = req.user_id {
-        users::delete_user_by_id(pool, &user_id).await
+    if let Some(user_id) = req.user_id {
+        match users::delete_user_by_id(pool, &user_id, &pre).await? {
+            Some(user_id) => Ok(Json(UserDeleteRes { user_id })),
+            None => {
+                let current = users::get_user_by_id(pool, &user_id, false).await;
+                Err(no_user_error(current, &user_id.to_string()))
+            }
+        }
     } else {
-        users::delete_user_by_email(pool, &req.email.unwrap()).await
+        let mut email = req.email.unwrap();
+        normalize_str(&mut email, true);
+        match users::delete_user_by_email(pool, &email, &pre).await? {
+            Some(user_id) => Ok(Json(UserDeleteRes { user_id })),
+            None => {
+                let current = users::get_user_by_email(pool, &email).await;
+                Err(no_user_error(current, &email))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct GetUserParams {
+    /// Also report the user if deleted.
+    #[serde(default)]
+    include_deleted: bool,
+}
+
+/// Get a user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "ID of the user"),
+        GetUserParams,
+        ("If-None-Match" = Option<String>, Header, description = "ETags known to the client"),
+    ),
+    responses(
+       (status = 200, description = "The user", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 304, description = "User unchanged since the given ETag"),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+    Query(params): Query<GetUserParams>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    tracing::info!("get_user: {user_id} {params:?}");
+    let res = users::get_user_by_id(&state.pool, &user_id, params.include_deleted).await;
+    get_user_response(res, &user_id.to_string(), &headers)
+}
+
+/// Get a user by email
+#[utoipa::path(
+    get,
+    path = "/users/by-email/{email}",
+    params(
+        ("email" = String, Path, description = "Email of the user"),
+        ("If-None-Match" = Option<String>, Header, description = "ETags known to the client"),
+    ),
+    responses(
+       (status = 200, description = "The user", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 304, description = "User unchanged since the given ETag"),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user_by_email(
+    state: State<AppState>,
+    Path(mut email): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    normalize_str(&mut email, true);
+    tracing::info!("get_user_by_email: {email}");
+    let res = users::get_user_by_email(&state.pool, &email).await;
+    get_user_response(res, &email, &headers)
+}
+
+/// Request content to patch a user. Only the given fields are updated.
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
+pub struct UserPatchReq {
+    /// New email of the user
+    #[schema(format = "email", max_length = 254)]
+    #[validate(
+        email(message = "must be a valid email address"),
+        length(max = "EMAIL_MAX_LEN", message = "must be at most 254 characters")
+    )]
+    pub email: Option<String>,
+    /// New name of the user
+    #[schema(min_length = 1, max_length = 100)]
+    #[validate(length(
+        min = 1,
+        max = "NAME_MAX_LEN",
+        message = "must have between 1 and 100 characters"
+    ))]
+    pub name: Option<String>,
+}
+
+impl Normalize for UserPatchReq {
+    fn normalize(&mut self) {
+        if let Some(email) = &mut self.email {
+            normalize_str(email, true);
+        }
+        if let Some(name) = &mut self.name {
+            normalize_str(name, false);
+        }
+    }
+}
+
+/// Update some fields of a user
+#[utoipa::path(
+    patch,
+    path = "/users/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "ID of the user"),
+        ("If-Match" = Option<String>, Header, description = "Only if the user has one of these ETags"),
+        ("If-None-Match" = Option<String>, Header, description = "Only if the user has none of these ETags"),
+    ),
+    request_body = UserPatchReq,
+    responses(
+       (status = 200, description = "User updated", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails),
+       (status = 412, description = "User modified since the given ETag", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn patch_user(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+    ValidatedJson(req): ValidatedJson<UserPatchReq>,
+) -> ApiResult<Response> {
+    tracing::info!("patch_user: {user_id} {req:?}");
+    let pool = &state.pool;
+    let pre = etag::preconditions(&headers);
+    let (email, name) = (req.email.as_deref(), req.name.as_deref());
+    match users::update_user(pool, &user_id, email, name, &pre).await? {
+        Some(user) => Ok(user_response(&user)),
+        None => {
+            let current = users::get_user_by_id(pool, &user_id, false).await;
+            Err(no_user_error(current, &user_id.to_string()))
+        }
+    }
+}
+
+/// Delete a user
+///
+/// The user is only marked as deleted, so it can be restored until purged.
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}",
+    params(
+        ("user_id" = String, Path, description = "ID of the user"),
+        ("If-Match" = Option<String>, Header, description = "Only if the user has one of these ETags"),
+        ("If-None-Match" = Option<String>, Header, description = "Only if the user has none of these ETags"),
+    ),
+    responses(
+       (status = 200, description = "User deleted", body = UserDeleteRes),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails),
+       (status = 412, description = "User modified since the given ETag", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn delete_user_by_id(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+    headers: HeaderMap,
+) -> ApiResult<Json<UserDeleteRes>> {
+    tracing::info!("delete_user_by_id: {user_id}");
+    let pool = &state.pool;
+    let pre = etag::preconditions(&headers);
+    match users::delete_user_by_id(pool, &user_id, &pre).await? {
+        Some(user_id) => Ok(Json(UserDeleteRes { user_id })),
+        None => {
+            let current = users::get_user_by_id(pool, &user_id, false).await;
+            Err(no_user_error(current, &user_id.to_string()))
+        }
+    }
+}
+
+/// Get the change history of a user, oldest first
+///
+/// Each change is attributed to the `request.user_id` setting of its transaction
+/// (the principal making the change), if any, or else to the database user.
+/// Deleted users have a history too, e.g., of their deletion.
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/history",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Changes to the user", body = Vec<RowChange>),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user_history(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<RowChange>>> {
+    tracing::info!("get_user_history: {user_id}");
+    let pool = &state.pool;
+    if users::get_user_by_id(pool, &user_id, true).await?.is_none() {
+        return Err(ApiError::not_found(format!("User not found: {user_id}")));
+    }
+    let changes = row_history::get_row_history(pool, "usr", &user_id.to_string()).await?;
+    Ok(Json(changes))
+}
+
+/// Restore a deleted user
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/restore",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "User restored", body = UserRes,
+        headers(("etag" = String, description = "Version of the user"))),
+       (status = 404, description = "Deleted user not found", body = crate::server::error::ProblemDetails),
+       (status = 409, description = "Email taken by another user", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn restore_user(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Response> {
+    tracing::info!("restore_user: {user_id}");
+    match users::restore_user(&state.pool, &user_id).await? {
+        Some(user) => Ok(user_response(&user)),
+        None => Err(ApiError::not_found(format!(
+            "Deleted user not found: {user_id}"
+        ))),
+    }
+}
+
+/// Permanently remove a deleted user
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/purge",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "User purged", body = UserDeleteRes),
+       (status = 404, description = "Deleted user not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn purge_user(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<UserDeleteRes>> {
+    tracing::info!("purge_user: {user_id}");
+    match users::purge_user(&state.pool, &user_id).await? {
+        Some(user_id) => Ok(Json(UserDeleteRes { user_id })),
+        None => Err(ApiError::not_found(format!(
+            "Deleted user not found: {user_id}"
+        ))),
+    }
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[into_params(parameter_in = Query)]
+pub struct PurgeParams {
+    /// Only users deleted before this time (RFC 3339). By default, all deleted users.
+    #[param(value_type = Option<String>)]
+    deleted_before: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct PurgeRes {
+    /// IDs of the removed users.
+    #[schema(value_type = Vec<String>)]
+    pub user_ids: Vec<uuid::Uuid>,
+}
+
+/// Permanently remove deleted users
+#[utoipa::path(
+    post,
+    path = "/users/purge",
+    params(PurgeParams),
+    responses(
+       (status = 200, description = "Users purged", body = PurgeRes)
+    )
+)]
+pub async fn purge_users(
+    state: State<AppState>,
+    Query(params): Query<PurgeParams>,
+) -> ApiResult<Json<PurgeRes>> {
+    tracing::info!("purge_users: {params:?}");
+    let user_ids = users::purge_users(&state.pool, params.deleted_before).await?;
+    Ok(Json(PurgeRes { user_ids }))
+}
+
+/// The user, or a 404 error if there is no such (not deleted) user.
+pub(crate) async fn existing_user(pool: &sqlx::PgPool, user_id: &uuid::Uuid) -> ApiResult<User> {
+    match users::get_user_by_id(pool, user_id, false).await? {
+        Some(user) => Ok(user),
+        None => Err(ApiError::not_found(format!("User not found: {user_id}"))),
+    }
+}
+
+/// The user, with its version in the `ETag` header.
+fn user_response(user: &User) -> Response {
+    let etag = [(header::ETAG, user.etag())];
+    (etag, Json(UserRes::from_user(user))).into_response()
+}
+
+/// Response to a GET of a user: 304 if it matches `If-None-Match`.
+fn get_user_response(
+    res: anyhow::Result<Option<User>>,
+    key: &str,
+    headers: &HeaderMap,
+) -> ApiResult<Response> {
+    let Some(user) = res? else {
+        return Err(ApiError::not_found(format!("User not found: {key}")));
     };
-    match res {
-        Ok(user_id) => Json(UserDeleteRes { user_id }).into_response(),
-        Err(e) => {
-            log::error!("Error: {:?}", e);
-            (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+    let etag = user.etag();
+    if etag::is_not_modified(headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+    }
+    Ok(user_response(&user))
+}
+
+/// Error for an update or delete that affected no user, given the current one, if any:
+/// 412 if it exists, as the `If-Match`/`If-None-Match` preconditions were not met, otherwise 404.
+fn no_user_error(current: anyhow::Result<Option<User>>, key: &str) -> ApiError {
+    match current {
+        Ok(Some(_)) => ApiError::new(
+            StatusCode::PRECONDITION_FAILED,
+            format!("Precondition failed for user: {key}"),
+        ),
+        Ok(None) => ApiError::not_found(format!("User not found: {key}")),
+        Err(e) => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn after_must_be_a_user_id() {
+        let params = |uri: &str| Query::<QueryParams>::try_from_uri(&uri.parse().unwrap());
+        let id = uuid::Uuid::new_v4();
+        let Query(ok) = params(&format!("/users?after={id}")).unwrap();
+        assert_eq!(ok.after, Some(id));
+        for after in ["nope", "123", &id.to_string()[1..]] {
+            assert!(params(&format!("/users?after={after}")).is_err(), "{after}");
         }
     }
 }
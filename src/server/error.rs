@@ -0,0 +1,195 @@
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::openapi::{ContentBuilder, Ref, ResponseBuilder};
+use utoipa::ToSchema;
+
+use crate::db::BadInput;
+use crate::server::validation::field_errors;
+use crate::server::{prometheus, request_id};
+
+/// Body of error responses, as in RFC 7807 ("problem details").
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ProblemDetails {
+    /// Always `about:blank`, so `title` is the HTTP status phrase.
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// SQLSTATE code, for errors reported by the database.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sqlstate: Option<String>,
+    /// Name of the violated constraint, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+    /// Validation failures by field, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<BTreeMap<String, Vec<String>>>,
+    /// Identifies the request in the server logs.
+    pub request_id: String,
+}
+
+/// Error returned by the handlers, rendered as [`ProblemDetails`].
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub detail: String,
+    pub sqlstate: Option<String>,
+    pub constraint: Option<String>,
+    pub errors: Option<BTreeMap<String, Vec<String>>>,
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+impl ApiError {
+    pub fn new(status: StatusCode, detail: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            detail: detail.into(),
+            sqlstate: None,
+            constraint: None,
+            errors: None,
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, detail)
+    }
+
+    pub fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, detail)
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, detail)
+    }
+}
+
+/// Status for a database error, based on its SQLSTATE code.
+/// See https://www.postgresql.org/docs/current/errcodes-appendix.html
+fn status_for_sqlstate(code: &str) -> StatusCode {
+    match code {
+        "23505" => StatusCode::CONFLICT,             // unique_violation
+        "23503" => StatusCode::UNPROCESSABLE_ENTITY, // foreign_key_violation
+        "57014" => StatusCode::GATEWAY_TIMEOUT,      // query_canceled (e.g., statement_timeout)
+        "40001" | "40P01" => StatusCode::CONFLICT,   // serialization_failure, deadlock_detected
+        "42501" => StatusCode::FORBIDDEN,            // insufficient_privilege
+        _ => match &code[..2] {
+            // data exception, integrity constraint, invalid transaction state, syntax/access rule
+            "22" | "23" | "25" | "42" => StatusCode::BAD_REQUEST,
+            // insufficient resources, operator intervention
+            "53" | "57" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        },
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        let detail = e.to_string();
+        match e {
+            sqlx::Error::Database(db_err) => {
+                let sqlstate = db_err.code().map(|c| c.to_string());
+                if let Some(sqlstate) = &sqlstate {
+                    prometheus::count_db_error(sqlstate);
+                }
+                let status = sqlstate
+                    .as_deref()
+                    .map(status_for_sqlstate)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                ApiError {
+                    status,
+                    detail,
+                    sqlstate,
+                    constraint: db_err.constraint().map(|c| c.to_string()),
+                    errors: None,
+                }
+            }
+            sqlx::Error::RowNotFound => Self::not_found(detail),
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                Self::new(StatusCode::SERVICE_UNAVAILABLE, detail)
+            }
+            _ => Self::internal(detail),
+        }
+    }
+}
+
+/// Errors from the `db` layer are mapped according to the underlying `sqlx::Error`, if any,
+/// or as 400 if due to invalid input (`BadInput`); any others are failures of the service.
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<sqlx::Error>() {
+            Ok(e) => return e.into(),
+            Err(e) => e,
+        };
+        match e.downcast::<BadInput>() {
+            Ok(e) => Self::bad_request(e.0),
+            Err(e) => Self::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<validator::ValidationErrors> for ApiError {
+    fn from(e: validator::ValidationErrors) -> Self {
+        ApiError {
+            errors: Some(field_errors(&e)),
+            ..Self::new(StatusCode::UNPROCESSABLE_ENTITY, "Invalid request content")
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let request_id = request_id::current().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        if self.status.is_server_error() {
+            tracing::error!("{}: {}", self.status, self.detail);
+        } else {
+            tracing::warn!("{}: {}", self.status, self.detail);
+        }
+        let body = ProblemDetails {
+            type_: "about:blank".to_string(),
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+            status: self.status.as_u16(),
+            detail: self.detail,
+            sqlstate: self.sqlstate,
+            constraint: self.constraint,
+            errors: self.errors,
+            request_id,
+        };
+        let content_type = [(header::CONTENT_TYPE, "application/problem+json")];
+        (self.status, content_type, Json(body)).into_response()
+    }
+}
+
+/// Adds a `default` response with [`ProblemDetails`] to all operations in the spec.
+pub struct ErrorResponses;
+
+impl utoipa::Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let response = ResponseBuilder::new()
+            .description("Error")
+            .content(
+                "application/problem+json",
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ProblemDetails"))
+                    .build(),
+            )
+            .build();
+        for path_item in openapi.paths.paths.values_mut() {
+            for operation in path_item.operations.values_mut() {
+                operation
+                    .responses
+                    .responses
+                    .entry("default".to_string())
+                    .or_insert_with(|| response.clone().into());
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Conditional requests on users, based on their `ETag` (see `User::etag`).
+
+use axum::http::{header, HeaderMap, HeaderName};
+use chrono::{DateTime, Utc};
+
+use crate::db::users::Preconditions;
+
+/// Entity tags listed in the header, or `None` if absent or `*`.
+fn etags(headers: &HeaderMap, name: HeaderName) -> Option<Option<Vec<String>>> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+    let tags: Vec<&str> = values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .collect();
+    if tags.contains(&"*") {
+        return Some(None);
+    }
+    Some(Some(tags.iter().map(|t| t.to_string()).collect()))
+}
+
+/// The version encoded in an entity tag, if valid.
+fn version(etag: &str) -> Option<DateTime<Utc>> {
+    let micros: i64 = etag.strip_prefix('"')?.strip_suffix('"')?.parse().ok()?;
+    let nanos = (micros.rem_euclid(1_000_000) * 1000) as u32;
+    DateTime::from_timestamp(micros.div_euclid(1_000_000), nanos)
+}
+
+/// Weak tags (`W/"..."`) are only accepted with the weak comparison of `If-None-Match`.
+fn versions(etags: &[String], weak: bool) -> Vec<DateTime<Utc>> {
+    etags
+        .iter()
+        .map(|t| if weak { without_weak_prefix(t) } else { t })
+        .filter_map(version)
+        .collect()
+}
+
+fn without_weak_prefix(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// Preconditions for an update or delete, from `If-Match` and `If-None-Match`.
+/// Tags that are not from this service are kept out, so they never match.
+pub fn preconditions(headers: &HeaderMap) -> Preconditions {
+    let mut pre = Preconditions::default();
+    if let Some(Some(tags)) = etags(headers, header::IF_MATCH) {
+        pre.if_match = Some(versions(&tags, false));
+    }
+    match etags(headers, header::IF_NONE_MATCH) {
+        // `*`: no existing user satisfies it
+        Some(None) => pre.if_match = Some(vec![]),
+        Some(Some(tags)) => pre.if_none_match = Some(versions(&tags, true)),
+        None => (),
+    }
+    pre
+}
+
+/// Whether `If-None-Match` matches the given entity tag, so a GET can be answered with 304.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str) -> bool {
+    match etags(headers, header::IF_NONE_MATCH) {
+        Some(None) => true,
+        Some(Some(tags)) => tags.iter().any(|t| without_weak_prefix(t) == etag),
+        None => false,
+    }
+}
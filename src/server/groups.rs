@@ -0,0 +1,287 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    routing::{get, patch, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::groups;
+use crate::models::Group;
+use crate::models::Permission::{UsersRead, UsersWrite};
+use crate::server::auth::require;
+use crate::server::database::{existing_user, UserRes};
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::validation::{normalize_str, Normalize, ValidatedJson};
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/groups",
+            get(get_groups)
+                .route_layer(require(UsersRead))
+                .merge(post(add_group).route_layer(require(UsersWrite))),
+        )
+        .route(
+            "/groups/:group_id",
+            get(get_group).route_layer(require(UsersRead)).merge(
+                patch(patch_group)
+                    .delete(delete_group)
+                    .route_layer(require(UsersWrite)),
+            ),
+        )
+        .route(
+            "/groups/:group_id/members",
+            get(get_members).route_layer(require(UsersRead)),
+        )
+        .route(
+            "/groups/:group_id/members/:user_id",
+            put(add_member)
+                .delete(remove_member)
+                .route_layer(require(UsersWrite)),
+        )
+        .route(
+            "/users/:user_id/groups",
+            get(get_user_groups).route_layer(require(UsersRead)),
+        )
+        .with_state(app_state)
+}
+
+/// Request content to add a group
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
+pub struct GroupPostReq {
+    /// Name of the group, e.g., `cruise-2024-03`
+    #[schema(min_length = 1, max_length = 64)]
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: String,
+    #[schema(max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub description: Option<String>,
+}
+
+impl Normalize for GroupPostReq {
+    fn normalize(&mut self) {
+        normalize_str(&mut self.name, false);
+    }
+}
+
+/// Request content to patch a group. Only the given fields are updated.
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
+pub struct GroupPatchReq {
+    #[schema(min_length = 1, max_length = 64)]
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: Option<String>,
+    #[schema(max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub description: Option<String>,
+}
+
+impl Normalize for GroupPatchReq {
+    fn normalize(&mut self) {
+        if let Some(name) = &mut self.name {
+            normalize_str(name, false);
+        }
+    }
+}
+
+/// Get all groups
+#[utoipa::path(
+    get,
+    path = "/groups",
+    responses(
+       (status = 200, description = "List of groups", body = Vec<Group>)
+    )
+)]
+pub async fn get_groups(state: State<AppState>) -> ApiResult<Json<Vec<Group>>> {
+    tracing::info!("get_groups");
+    Ok(Json(groups::get_groups(&state.pool).await?))
+}
+
+/// Add a group
+#[utoipa::path(
+    post,
+    path = "/groups",
+    request_body = GroupPostReq,
+    responses(
+       (status = 200, description = "Group added", body = Group),
+       (status = 409, description = "Name already taken", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn add_group(
+    state: State<AppState>,
+    ValidatedJson(req): ValidatedJson<GroupPostReq>,
+) -> ApiResult<Json<Group>> {
+    tracing::info!("add_group: {req:?}");
+    let group = groups::insert_group(&state.pool, &req.name, req.description.as_deref()).await?;
+    Ok(Json(group))
+}
+
+/// Get a group
+#[utoipa::path(
+    get,
+    path = "/groups/{group_id}",
+    params(("group_id" = String, Path, description = "ID of the group")),
+    responses(
+       (status = 200, description = "The group", body = Group),
+       (status = 404, description = "Group not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_group(
+    state: State<AppState>,
+    Path(group_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Group>> {
+    tracing::info!("get_group: {group_id}");
+    Ok(Json(existing_group(&state.pool, &group_id).await?))
+}
+
+/// Update some fields of a group
+#[utoipa::path(
+    patch,
+    path = "/groups/{group_id}",
+    params(("group_id" = String, Path, description = "ID of the group")),
+    request_body = GroupPatchReq,
+    responses(
+       (status = 200, description = "Group updated", body = Group),
+       (status = 404, description = "Group not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn patch_group(
+    state: State<AppState>,
+    Path(group_id): Path<uuid::Uuid>,
+    ValidatedJson(req): ValidatedJson<GroupPatchReq>,
+) -> ApiResult<Json<Group>> {
+    tracing::info!("patch_group: {group_id} {req:?}");
+    let (name, description) = (req.name.as_deref(), req.description.as_deref());
+    match groups::update_group(&state.pool, &group_id, name, description).await? {
+        Some(group) => Ok(Json(group)),
+        None => Err(group_not_found(&group_id)),
+    }
+}
+
+/// Delete a group, removing all its memberships
+#[utoipa::path(
+    delete,
+    path = "/groups/{group_id}",
+    params(("group_id" = String, Path, description = "ID of the group")),
+    responses(
+       (status = 204, description = "Group deleted"),
+       (status = 404, description = "Group not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn delete_group(
+    state: State<AppState>,
+    Path(group_id): Path<uuid::Uuid>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("delete_group: {group_id}");
+    match groups::delete_group(&state.pool, &group_id).await? {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err(group_not_found(&group_id)),
+    }
+}
+
+/// Get the members of a group
+#[utoipa::path(
+    get,
+    path = "/groups/{group_id}/members",
+    params(("group_id" = String, Path, description = "ID of the group")),
+    responses(
+       (status = 200, description = "Members of the group", body = Vec<UserRes>),
+       (status = 404, description = "Group not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_members(
+    state: State<AppState>,
+    Path(group_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<UserRes>>> {
+    tracing::info!("get_members: {group_id}");
+    let pool = &state.pool;
+    existing_group(pool, &group_id).await?;
+    let users = groups::get_members(pool, &group_id).await?;
+    Ok(Json(users.iter().map(UserRes::from_user).collect()))
+}
+
+/// Add a user to a group
+#[utoipa::path(
+    put,
+    path = "/groups/{group_id}/members/{user_id}",
+    params(
+        ("group_id" = String, Path, description = "ID of the group"),
+        ("user_id" = String, Path, description = "ID of the user"),
+    ),
+    responses(
+       (status = 204, description = "User added (or already a member)"),
+       (status = 404, description = "Group or user not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn add_member(
+    state: State<AppState>,
+    Path((group_id, user_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("add_member: {group_id} {user_id}");
+    let pool = &state.pool;
+    existing_group(pool, &group_id).await?;
+    existing_user(pool, &user_id).await?;
+    groups::add_member(pool, &group_id, &user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Remove a user from a group
+#[utoipa::path(
+    delete,
+    path = "/groups/{group_id}/members/{user_id}",
+    params(
+        ("group_id" = String, Path, description = "ID of the group"),
+        ("user_id" = String, Path, description = "ID of the user"),
+    ),
+    responses(
+       (status = 204, description = "User removed"),
+       (status = 404, description = "User not a member of the group", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn remove_member(
+    state: State<AppState>,
+    Path((group_id, user_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("remove_member: {group_id} {user_id}");
+    if groups::remove_member(&state.pool, &group_id, &user_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!(
+            "User {user_id} not a member of group {group_id}"
+        )))
+    }
+}
+
+/// Get the groups a user is a member of
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/groups",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Groups of the user", body = Vec<Group>),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user_groups(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<Group>>> {
+    tracing::info!("get_user_groups: {user_id}");
+    let pool = &state.pool;
+    existing_user(pool, &user_id).await?;
+    Ok(Json(groups::get_user_groups(pool, &user_id).await?))
+}
+
+async fn existing_group(pool: &sqlx::PgPool, group_id: &uuid::Uuid) -> ApiResult<Group> {
+    match groups::get_group(pool, group_id).await? {
+        Some(group) => Ok(group),
+        None => Err(group_not_found(group_id)),
+    }
+}
+
+fn group_not_found(group_id: &uuid::Uuid) -> ApiError {
+    ApiError::not_found(format!("Group not found: {group_id}"))
+}
@@ -1,14 +1,25 @@
+use axum::extract::State;
+use axum::http::StatusCode;
 use axum::{routing, Json, Router};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use sysinfo::{CpuRefreshKind, MemoryRefreshKind, RefreshKind, System};
 use utoipa::ToSchema;
 
-pub fn create_router() -> Router {
+use crate::db::health::{check_database, pending_migrations};
+use crate::server::AppState;
+
+/// Time for the database to respond to the checks.
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn create_router(app_state: AppState) -> Router {
     Router::new()
         .route("/ping", routing::get(ping))
         .route("/health", routing::get(get_health))
+        .route("/health/live", routing::get(get_liveness))
+        .route("/health/ready", routing::get(get_readiness))
+        .with_state(app_state)
 }
 
 #[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
@@ -38,9 +49,17 @@ pub struct HealthStatus {
     pub cpus: usize,
     pub application: String,
     pub version: String,
+    /// Seconds since the service started.
+    pub uptime: u64,
+    /// Milliseconds for the database to respond to a `select 1`, if it did.
+    pub database_latency_ms: Option<f64>,
+    /// Version of the database server, if it responded.
+    pub database_version: Option<String>,
 }
 
-/// Get a basic status of the service.
+/// Get a basic status of the service, and of the database.
+///
+/// The status is 200 even if the database does not respond; see `/health/ready`.
 #[utoipa::path(
     get,
     path = "/health",
@@ -49,8 +68,119 @@ pub struct HealthStatus {
     )
 )]
 
-pub async fn get_health() -> Json<HealthStatus> {
-    Json(get_health_status())
+pub async fn get_health(State(state): State<AppState>) -> Json<HealthStatus> {
+    let mut status = get_health_status();
+    match check_database(&state.pool, DB_CHECK_TIMEOUT).await {
+        Ok(info) => {
+            status.database_latency_ms = Some(info.latency.as_secs_f64() * 1000.0);
+            status.database_version = Some(info.server_version);
+        }
+        Err(e) => tracing::warn!("get_health: {e}"),
+    }
+    Json(status)
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct Liveness {
+    /// Seconds since the service started.
+    pub uptime: u64,
+}
+
+/// Whether the service is running, regardless of the database (a liveness probe).
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+       (status = 200, description = "The service is running", body = Liveness)
+    )
+)]
+pub async fn get_liveness() -> Json<Liveness> {
+    Json(Liveness { uptime: uptime() })
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct Readiness {
+    /// Whether all the checks passed.
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct ReadinessCheck {
+    /// `database`, `pool`, or `migrations` (only with `--own-db`).
+    pub name: String,
+    pub ok: bool,
+    /// What was found, e.g., the latency of the database, or why the check failed.
+    pub detail: String,
+}
+
+impl ReadinessCheck {
+    fn new(name: &str, ok: bool, detail: String) -> Self {
+        ReadinessCheck {
+            name: name.to_string(),
+            ok,
+            detail,
+        }
+    }
+}
+
+/// Whether the service can handle requests (a readiness probe).
+///
+/// Checks that the database responds to a `select 1` in time,
+/// that not all the connections in the pool are in use,
+/// and, with `--own-db` (running the migrations), that all have been applied.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+       (status = 200, description = "Ready", body = Readiness),
+       (status = 503, description = "Not ready, with the failed checks", body = Readiness)
+    )
+)]
+pub async fn get_readiness(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let pool = &state.pool;
+    let mut checks = vec![];
+
+    let database = check_database(pool, DB_CHECK_TIMEOUT).await;
+    checks.push(match &database {
+        Ok(info) => ReadinessCheck::new("database", true, format!("{:?}", info.latency)),
+        Err(e) => ReadinessCheck::new("database", false, e.to_string()),
+    });
+
+    let max = pool.options().get_max_connections();
+    let in_use = pool.size().saturating_sub(pool.num_idle() as u32);
+    let detail = format!("{in_use} of {max} connections in use");
+    checks.push(ReadinessCheck::new("pool", in_use < max, detail));
+
+    if state.check_migrations {
+        checks.push(check_migrations(pool, database.is_ok()).await);
+    }
+
+    let ready = checks.iter().all(|c| c.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        tracing::warn!("get_readiness: {checks:?}");
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks }))
+}
+
+/// Whether all the migrations have been applied (only checked if the database responds).
+async fn check_migrations(pool: &sqlx::PgPool, database_ok: bool) -> ReadinessCheck {
+    if !database_ok {
+        return ReadinessCheck::new("migrations", false, "database not available".to_string());
+    }
+    match pending_migrations(pool).await {
+        Ok(pending) if pending.is_empty() => {
+            ReadinessCheck::new("migrations", true, "all applied".to_string())
+        }
+        Ok(pending) => {
+            let detail = format!("pending: {}", pending.join(", "));
+            ReadinessCheck::new("migrations", false, detail)
+        }
+        Err(e) => ReadinessCheck::new("migrations", false, e.to_string()),
+    }
 }
 
 pub fn get_health_status() -> HealthStatus {
@@ -68,8 +198,21 @@ pub fn get_health_status() -> HealthStatus {
         cpus: sys.cpus().len(),
         application: env!("CARGO_PKG_NAME").to_string(),
         version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime: uptime(),
+        database_latency_ms: None,
+        database_version: None,
     };
     let duration = start.elapsed();
-    log::info!("get_health_status (took: {duration:?})");
+    tracing::info!("get_health_status (took: {duration:?})");
     status
 }
+
+/// Seconds since this process started.
+fn uptime() -> u64 {
+    let Ok(pid) = sysinfo::get_current_pid() else {
+        return 0;
+    };
+    let mut sys = System::new();
+    sys.refresh_process(pid);
+    sys.process(pid).map(|p| p.run_time()).unwrap_or_default()
+}
@@ -0,0 +1,44 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+
+use crate::db::history::{HistoryFilter, QueryRecord};
+use crate::models::Permission;
+use crate::server::auth::{require, Principal};
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/history", get(get_history))
+        .route_layer(require(Permission::Query))
+        .with_state(app_state)
+}
+
+/// Get the history of generic queries, most recent first.
+///
+/// Only the queries of the caller, unless an admin.
+#[utoipa::path(
+    get,
+    path = "/history",
+    params(HistoryFilter),
+    responses(
+       (status = 200, description = "Query history", body = Vec<QueryRecord>)
+    )
+)]
+pub async fn get_history(
+    state: State<AppState>,
+    principal: Option<Principal>,
+    Query(mut filter): Query<HistoryFilter>,
+) -> ApiResult<Json<Vec<QueryRecord>>> {
+    if let Some(principal) = principal.filter(|p| !p.has_permission(Permission::Admin)) {
+        filter.caller = Some(principal.subject);
+    }
+    tracing::info!("get_history: {filter:?}");
+    match state.history.read(&filter).await {
+        Ok(records) => Ok(Json(records)),
+        Err(e) => Err(ApiError::internal(e.to_string())),
+    }
+}
@@ -0,0 +1,395 @@
+//! Validation of JWTs given as bearer tokens, against keys configured locally
+//! (a shared secret, a PEM public key, or a JWKS file).
+
+use anyhow::{anyhow, bail, Context};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+
+use crate::config::JwtConfig;
+use crate::server::auth::Principal;
+
+/// The only algorithms accepted.
+const ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::RS256, Algorithm::ES256];
+
+struct JwtKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+pub struct JwtValidator {
+    keys: Vec<JwtKey>,
+    audience: Option<String>,
+    issuer: Option<String>,
+    leeway: u64,
+}
+
+/// The claims mapped to the principal.
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    /// Space-separated scopes, as in OAuth 2.0.
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// Names of roles, for their permissions.
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+impl JwtValidator {
+    /// The validator for the configured keys, or `None` if JWTs are not enabled.
+    pub fn new(config: &JwtConfig) -> anyhow::Result<Option<Self>> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+        let algorithm = config
+            .algorithm
+            .as_deref()
+            .map(parse_algorithm)
+            .transpose()?;
+
+        let mut keys = vec![];
+        if let Some(secret) = &config.secret {
+            keys.push(JwtKey {
+                kid: None,
+                algorithm: Algorithm::HS256,
+                key: DecodingKey::from_secret(secret.as_bytes()),
+            });
+        }
+        if let Some(file) = &config.public_key_file {
+            let pem = std::fs::read(file).with_context(|| format!("reading {file:?}"))?;
+            let (algorithm, key) = match algorithm {
+                Some(Algorithm::RS256) => (Algorithm::RS256, DecodingKey::from_rsa_pem(&pem)?),
+                Some(Algorithm::ES256) => (Algorithm::ES256, DecodingKey::from_ec_pem(&pem)?),
+                _ => bail!("SQLXUM_JWT_ALGORITHM must be RS256 or ES256 for a public key"),
+            };
+            keys.push(JwtKey {
+                kid: None,
+                algorithm,
+                key,
+            });
+        }
+        if let Some(file) = &config.jwks_file {
+            let json =
+                std::fs::read_to_string(file).with_context(|| format!("reading {file:?}"))?;
+            let jwks: JwkSet = serde_json::from_str(&json).with_context(|| format!("{file:?}"))?;
+            for jwk in &jwks.keys {
+                let algorithm = match (jwk.common.key_algorithm, &jwk.algorithm) {
+                    (Some(alg), _) => match parse_algorithm(&alg.to_string()) {
+                        Ok(algorithm) => algorithm,
+                        Err(e) => {
+                            let kid = jwk.common.key_id.as_deref().unwrap_or_default();
+                            tracing::warn!("{file:?}: skipping key '{kid}': {e}");
+                            continue;
+                        }
+                    },
+                    (None, AlgorithmParameters::RSA(_)) => Algorithm::RS256,
+                    (None, AlgorithmParameters::EllipticCurve(_)) => Algorithm::ES256,
+                    (None, AlgorithmParameters::OctetKey(_)) => Algorithm::HS256,
+                    (None, _) => algorithm.ok_or_else(|| anyhow!("{file:?}: key without alg"))?,
+                };
+                keys.push(JwtKey {
+                    kid: jwk.common.key_id.clone(),
+                    algorithm,
+                    key: DecodingKey::from_jwk(jwk)?,
+                });
+            }
+        }
+        tracing::info!("JWTs accepted, with {} key(s)", keys.len());
+        Ok(Some(JwtValidator {
+            keys,
+            audience: config.audience.clone(),
+            issuer: config.issuer.clone(),
+            leeway: config.leeway.as_secs(),
+        }))
+    }
+
+    /// The principal for the token, if it has a valid signature and claims.
+    /// The candidate keys are those with the algorithm in the token header, and with its
+    /// `kid` (if both have one), tried in turn until one verifies the signature.
+    pub fn validate(&self, token: &str) -> anyhow::Result<Principal> {
+        let header = decode_header(token)?;
+        let kid_matches = |k: &&JwtKey| match (&k.kid, &header.kid) {
+            (Some(kid), Some(token_kid)) => kid == token_kid,
+            _ => true,
+        };
+        let mut error = anyhow!("no key for the token");
+        for key in self
+            .keys
+            .iter()
+            .filter(|k| k.algorithm == header.alg)
+            .filter(kid_matches)
+        {
+            match decode::<Claims>(token, &key.key, &self.validation(key.algorithm)) {
+                Ok(data) => return Ok(principal(data.claims)),
+                Err(e) if *e.kind() == ErrorKind::InvalidSignature => error = e.into(),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(error)
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = self.leeway;
+        let mut required = vec!["exp", "sub"];
+        match &self.audience {
+            Some(audience) => {
+                validation.set_audience(&[audience]);
+                required.push("aud");
+            }
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        validation.set_required_spec_claims(&required);
+        validation
+    }
+}
+
+fn principal(claims: Claims) -> Principal {
+    let mut scopes = claims.scopes;
+    if let Some(scope) = claims.scope {
+        scopes.extend(scope.split_whitespace().map(str::to_string));
+    }
+    Principal::new(claims.sub, claims.roles, scopes)
+}
+
+fn parse_algorithm(name: &str) -> anyhow::Result<Algorithm> {
+    name.parse()
+        .ok()
+        .filter(|alg| ALGORITHMS.contains(alg))
+        .ok_or_else(|| anyhow!("unsupported JWT algorithm '{name}' (HS256, RS256, or ES256)"))
+}
+
+/// With the keys in `testdata/jwt`, generated locally with `openssl`
+/// (the JWKS has the EC public key, and the RSA one with an unsupported `alg`).
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const SECRET: &str = "a secret for the tests";
+
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/jwt")
+            .join(name)
+    }
+
+    fn config() -> JwtConfig {
+        JwtConfig {
+            algorithm: None,
+            secret: None,
+            public_key_file: None,
+            jwks_file: None,
+            audience: None,
+            issuer: None,
+            leeway: Duration::from_secs(0),
+        }
+    }
+
+    fn validator(config: JwtConfig) -> JwtValidator {
+        JwtValidator::new(&config).unwrap().unwrap()
+    }
+
+    fn hs256() -> JwtValidator {
+        validator(JwtConfig {
+            secret: Some(SECRET.to_string()),
+            ..config()
+        })
+    }
+
+    fn rs256() -> JwtValidator {
+        validator(JwtConfig {
+            algorithm: Some("RS256".to_string()),
+            public_key_file: Some(testdata("rsa.pub.pem")),
+            ..config()
+        })
+    }
+
+    fn jwks() -> JwtValidator {
+        validator(JwtConfig {
+            jwks_file: Some(testdata("jwks.json")),
+            ..config()
+        })
+    }
+
+    fn claims(extra: Value) -> Value {
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let mut claims = json!({"sub": "alice", "exp": exp});
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        claims
+    }
+
+    fn sign(header: Header, claims: &Value, key: &EncodingKey) -> String {
+        encode(&header, claims, key).unwrap()
+    }
+
+    fn sign_hs256(claims: &Value) -> String {
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        sign(Header::new(Algorithm::HS256), claims, &key)
+    }
+
+    fn sign_rs256(claims: &Value) -> String {
+        let pem = std::fs::read(testdata("rsa.pem")).unwrap();
+        let key = EncodingKey::from_rsa_pem(&pem).unwrap();
+        sign(Header::new(Algorithm::RS256), claims, &key)
+    }
+
+    fn sign_es256(claims: &Value, kid: &str) -> String {
+        let pem = std::fs::read(testdata("ec.pem")).unwrap();
+        let key = EncodingKey::from_ec_pem(&pem).unwrap();
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(kid.to_string());
+        sign(header, claims, &key)
+    }
+
+    #[test]
+    fn accepts_hs256() {
+        let token = sign_hs256(&claims(
+            json!({"scope": "query users:read", "roles": ["r"]}),
+        ));
+        let principal = hs256().validate(&token).unwrap();
+        assert_eq!(principal.subject, "alice");
+        assert_eq!(principal.permissions, ["query", "users:read"]);
+        assert_eq!(principal.roles, ["r"]);
+    }
+
+    #[test]
+    fn accepts_rs256() {
+        let token = sign_rs256(&claims(json!({"scopes": ["query"]})));
+        let principal = rs256().validate(&token).unwrap();
+        assert_eq!(principal.subject, "alice");
+        assert_eq!(principal.permissions, ["query"]);
+    }
+
+    #[test]
+    fn accepts_es256_by_kid() {
+        let token = sign_es256(&claims(json!({})), "ec1");
+        assert_eq!(jwks().validate(&token).unwrap().subject, "alice");
+    }
+
+    #[test]
+    fn skips_jwks_key_with_unsupported_alg() {
+        let validator = jwks();
+        assert_eq!(validator.keys.len(), 1);
+        assert_eq!(validator.keys[0].kid.as_deref(), Some("ec1"));
+    }
+
+    #[test]
+    fn rejects_expired() {
+        let exp = chrono::Utc::now().timestamp() - 60;
+        let token = sign_hs256(&claims(json!({"exp": exp})));
+        assert!(hs256().validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_aud() {
+        let validator = validator(JwtConfig {
+            secret: Some(SECRET.to_string()),
+            audience: Some("sqlxum".to_string()),
+            ..config()
+        });
+        let token = sign_hs256(&claims(json!({"aud": "sqlxum"})));
+        assert!(validator.validate(&token).is_ok());
+        let token = sign_hs256(&claims(json!({"aud": "other"})));
+        assert!(validator.validate(&token).is_err());
+        let token = sign_hs256(&claims(json!({})));
+        assert!(validator.validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_iss() {
+        let validator = validator(JwtConfig {
+            secret: Some(SECRET.to_string()),
+            issuer: Some("https://issuer.example.net".to_string()),
+            ..config()
+        });
+        let token = sign_hs256(&claims(json!({"iss": "https://issuer.example.net"})));
+        assert!(validator.validate(&token).is_ok());
+        let token = sign_hs256(&claims(json!({"iss": "https://other.example.net"})));
+        assert!(validator.validate(&token).is_err());
+        let token = sign_hs256(&claims(json!({})));
+        assert!(validator.validate(&token).is_err());
+    }
+
+    #[test]
+    fn accepts_token_kid_with_key_without_kid() {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("any".to_string());
+        let key = EncodingKey::from_secret(SECRET.as_bytes());
+        let token = sign(header, &claims(json!({})), &key);
+        assert_eq!(hs256().validate(&token).unwrap().subject, "alice");
+    }
+
+    #[test]
+    fn tries_each_key_with_the_alg() {
+        let hs256_key = |secret: &str| JwtKey {
+            kid: None,
+            algorithm: Algorithm::HS256,
+            key: DecodingKey::from_secret(secret.as_bytes()),
+        };
+        let validator = JwtValidator {
+            keys: vec![hs256_key("another secret"), hs256_key(SECRET)],
+            audience: None,
+            issuer: None,
+            leeway: 0,
+        };
+        let token = sign_hs256(&claims(json!({})));
+        assert_eq!(validator.validate(&token).unwrap().subject, "alice");
+
+        let key = EncodingKey::from_secret(b"a third secret");
+        let token = sign(Header::new(Algorithm::HS256), &claims(json!({})), &key);
+        assert!(validator.validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_kid() {
+        let token = sign_es256(&claims(json!({})), "unknown");
+        assert!(jwks().validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatched_alg() {
+        // signed with HS256 using the RSA public key as the secret
+        let public_key = std::fs::read(testdata("rsa.pub.pem")).unwrap();
+        let key = EncodingKey::from_secret(&public_key);
+        let token = sign(Header::new(Algorithm::HS256), &claims(json!({})), &key);
+        assert!(rs256().validate(&token).is_err());
+
+        let token = sign_rs256(&claims(json!({})));
+        assert!(hs256().validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_sub() {
+        let exp = chrono::Utc::now().timestamp() + 300;
+        let token = sign_hs256(&json!({ "exp": exp }));
+        assert!(hs256().validate(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_algorithm_setting() {
+        let config = JwtConfig {
+            algorithm: Some("PS512".to_string()),
+            public_key_file: Some(testdata("rsa.pub.pem")),
+            ..config()
+        };
+        assert!(JwtValidator::new(&config).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! HTTP middleware for the whole service: request IDs and access log, and, per `HttpConfig`,
+//! CORS, response compression, request body size limit, and request timeout.
+
+use anyhow::Context;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode};
+use axum::{middleware, BoxError, Router};
+use tower::timeout::error::Elapsed;
+use tower::ServiceBuilder;
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::limit::RequestBodyLimitLayer;
+
+use crate::config::HttpConfig;
+use crate::server::error::ApiError;
+use crate::server::request_id;
+
+/// Response headers the browser clients can read.
+const EXPOSED_HEADERS: [&str; 7] = [
+    "etag",
+    "retry-after",
+    "x-cache",
+    "x-ratelimit-limit",
+    "x-ratelimit-remaining",
+    "x-ratelimit-reset",
+    request_id::REQUEST_ID_HEADER,
+];
+
+pub fn add_layers(mut app: Router, config: &HttpConfig) -> anyhow::Result<Router> {
+    // the body limit replaces the default one of axum's extractors (2 MB)
+    app = app
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.body_limit));
+    if config.compression {
+        app = app.layer(CompressionLayer::new());
+    }
+    if !config.request_timeout.is_zero() {
+        let timeout = config.request_timeout;
+        app = app.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(move |e| timeout_error(e, timeout)))
+                .timeout(timeout),
+        );
+    }
+    // outermost, so that any response, including errors, has the CORS headers
+    if let Some(cors) = cors_layer(config)? {
+        app = app.layer(cors);
+    }
+    // outermost, so that all the requests are logged
+    app = app.layer(middleware::from_fn(request_id::track));
+    Ok(app)
+}
+
+/// 504 if the request timed out (the only error of the timeout layer otherwise).
+async fn timeout_error(e: BoxError, timeout: std::time::Duration) -> ApiError {
+    if e.is::<Elapsed>() {
+        ApiError::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Request not handled within {timeout:?}"),
+        )
+    } else {
+        ApiError::internal(e.to_string())
+    }
+}
+
+fn cors_layer(config: &HttpConfig) -> anyhow::Result<Option<CorsLayer>> {
+    let origins = &config.cors_allowed_origins;
+    if origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|o| HeaderValue::from_str(o).with_context(|| format!("CORS origin '{o}'")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+    let methods = config
+        .cors_allowed_methods
+        .iter()
+        .map(|m| Method::from_bytes(m.as_bytes()).with_context(|| format!("CORS method '{m}'")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let headers = config
+        .cors_allowed_headers
+        .iter()
+        .map(|h| HeaderName::from_bytes(h.as_bytes()).with_context(|| format!("CORS header '{h}'")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    tracing::info!("CORS allowed origins: {origins:?}");
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static)),
+    ))
+}
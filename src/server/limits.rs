@@ -0,0 +1,334 @@
+//! Rate limiting (a token bucket per client, and per IP address) and limits on the generic queries
+//! executing at once, so a single client cannot starve the others of database connections.
+//! Also the throttle of the login attempts per email and IP address.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lru::LruCache;
+
+use crate::config::LimitsConfig;
+use crate::server::auth::Principal;
+use crate::server::error::ApiError;
+use crate::server::AppState;
+
+/// Maximum number of clients whose buckets are kept; the least recently seen are dropped.
+const MAX_CLIENTS: usize = 10_000;
+
+pub struct Limits {
+    per_client: RateLimiter,
+    per_ip: RateLimiter,
+    login: RateLimiter,
+    trust_forwarded_for: bool,
+    max_queries: usize,
+    max_queries_per_client: usize,
+    queries: Mutex<Queries>,
+}
+
+/// Token buckets of the clients, refilled at `rate` tokens per second, up to `burst`.
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<LruCache<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Generic queries currently executing.
+#[derive(Default)]
+struct Queries {
+    total: usize,
+    per_client: HashMap<String, usize>,
+}
+
+/// Outcome of taking a token from a bucket.
+struct Taken {
+    allowed: bool,
+    remaining: f64,
+}
+
+impl RateLimiter {
+    fn new(rate: f64, burst: u32) -> Self {
+        RateLimiter {
+            rate,
+            burst: burst.max(1) as f64,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CLIENTS).unwrap())),
+        }
+    }
+
+    fn take(&self, client: &str) -> Taken {
+        self.take_at(client, Instant::now())
+    }
+
+    /// Takes a token for the client at the given time, after refilling the bucket.
+    fn take_at(&self, client: &str, now: Instant) -> Taken {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut(client.to_string(), || Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Taken {
+            allowed,
+            remaining: bucket.tokens,
+        }
+    }
+
+    /// Seconds until the bucket has `tokens`.
+    fn secs_until(&self, remaining: f64, tokens: f64) -> u64 {
+        ((tokens - remaining).max(0.0) / self.rate).ceil() as u64
+    }
+
+    /// Unless already reported by an inner limit, as the one closest to the client.
+    fn rate_headers(&self, headers: &mut HeaderMap, remaining: f64) {
+        if headers.contains_key("x-ratelimit-limit") {
+            return;
+        }
+        let reset = self.secs_until(remaining, self.burst);
+        headers.insert("x-ratelimit-limit", HeaderValue::from(self.burst as u64));
+        headers.insert("x-ratelimit-remaining", HeaderValue::from(remaining as u64));
+        headers.insert("x-ratelimit-reset", HeaderValue::from(reset));
+    }
+
+    /// Takes a token for the client before running the request, or responds 429.
+    async fn limit(&self, client: String, req: Request, next: Next) -> Response {
+        if self.rate <= 0.0 {
+            return next.run(req).await;
+        }
+        let taken = self.take(&client);
+        if !taken.allowed {
+            tracing::info!("rate_limit: {client} exceeded");
+            let retry_after = self.secs_until(taken.remaining, 1.0);
+            let mut res = too_many_requests("Rate limit exceeded".to_string(), retry_after);
+            self.rate_headers(res.headers_mut(), taken.remaining);
+            return res;
+        }
+        let mut res = next.run(req).await;
+        self.rate_headers(res.headers_mut(), taken.remaining);
+        res
+    }
+}
+
+impl Limits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Limits {
+            per_client: RateLimiter::new(config.rate, config.burst),
+            per_ip: RateLimiter::new(config.ip_rate, config.ip_burst),
+            login: RateLimiter::new(config.login_rate / 60.0, config.login_burst),
+            trust_forwarded_for: config.trust_forwarded_for,
+            max_queries: config.max_concurrent_queries,
+            max_queries_per_client: config.max_concurrent_queries_per_client,
+            queries: Mutex::default(),
+        }
+    }
+
+    /// The IP address of the client: the last one in `X-Forwarded-For` (as added by
+    /// the reverse proxy) if trusted, or else the address of the connection.
+    fn client_ip(&self, req: &Request) -> String {
+        let addr = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        self.ip(req.headers(), addr)
+    }
+
+    /// As [`Self::client_ip`], given the headers and the address of the connection.
+    fn ip(&self, headers: &HeaderMap, addr: Option<IpAddr>) -> String {
+        if self.trust_forwarded_for {
+            let forwarded = headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .next_back()
+                .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+            if let Some(ip) = forwarded {
+                return ip.to_string();
+            }
+        }
+        match addr {
+            Some(ip) => ip.to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// Takes a login attempt for the email from the IP address of the connection
+    /// (or as forwarded), or returns the 429 response when the attempts are exhausted.
+    pub fn throttle_login(
+        &self,
+        email: &str,
+        headers: &HeaderMap,
+        addr: IpAddr,
+    ) -> Option<Response> {
+        if self.login.rate <= 0.0 {
+            return None;
+        }
+        let client = format!("{email} {}", self.ip(headers, Some(addr)));
+        let taken = self.login.take(&client);
+        if taken.allowed {
+            return None;
+        }
+        tracing::info!("throttle_login: {client} exceeded");
+        let retry_after = self.login.secs_until(taken.remaining, 1.0);
+        Some(too_many_requests(
+            "Too many login attempts".to_string(),
+            retry_after,
+        ))
+    }
+
+    /// The client the limits apply to: the authenticated principal, or else the IP address.
+    fn client(&self, req: &Request) -> String {
+        match req.extensions().get::<Principal>() {
+            Some(principal) => principal.subject.clone(),
+            None => self.client_ip(req),
+        }
+    }
+
+    /// Registers a query from the client as executing, or returns the reason it cannot be.
+    fn start_query(self: &Arc<Self>, client: &str) -> Result<QueryGuard, String> {
+        let mut queries = self.queries.lock().unwrap();
+        if self.max_queries > 0 && queries.total >= self.max_queries {
+            return Err(format!("Too many queries executing ({})", self.max_queries));
+        }
+        let count = queries.per_client.entry(client.to_string()).or_default();
+        if self.max_queries_per_client > 0 && *count >= self.max_queries_per_client {
+            let max = self.max_queries_per_client;
+            return Err(format!("Too many queries executing for the client ({max})"));
+        }
+        *count += 1;
+        queries.total += 1;
+        Ok(QueryGuard {
+            limits: self.clone(),
+            client: client.to_string(),
+        })
+    }
+}
+
+/// Unregisters the query when dropped.
+struct QueryGuard {
+    limits: Arc<Limits>,
+    client: String,
+}
+
+impl Drop for QueryGuard {
+    fn drop(&mut self) {
+        let mut queries = self.limits.queries.lock().unwrap();
+        queries.total -= 1;
+        if let Some(count) = queries.per_client.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                queries.per_client.remove(&self.client);
+            }
+        }
+    }
+}
+
+fn too_many_requests(detail: String, retry_after: u64) -> Response {
+    let mut res = ApiError::new(StatusCode::TOO_MANY_REQUESTS, detail).into_response();
+    res.headers_mut()
+        .insert("retry-after", HeaderValue::from(retry_after.max(1)));
+    res
+}
+
+/// Middleware limiting the rate of requests per client (after authentication),
+/// reporting the limit in the `X-RateLimit-*` headers, and responding 429 with
+/// `Retry-After` when exceeded.
+pub async fn rate_limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let client = state.limits.client(&req);
+    state.limits.per_client.limit(client, req, next).await
+}
+
+/// Middleware limiting the rate of requests per IP address, before authentication,
+/// so the requests failing it are limited too. As [`rate_limit`] otherwise.
+pub async fn rate_limit_ip(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let client = state.limits.client_ip(&req);
+    state.limits.per_ip.limit(client, req, next).await
+}
+
+/// Middleware (for the routes running generic queries) limiting the queries executing
+/// at once, in total and per client, responding 429 with `Retry-After` when exceeded.
+pub async fn limit_queries(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let client = state.limits.client(&req);
+    let _guard = match state.limits.start_query(&client) {
+        Ok(guard) => guard,
+        Err(detail) => {
+            tracing::info!("limit_queries: {client}: {detail}");
+            return too_many_requests(detail, 1);
+        }
+    };
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn allowed(limiter: &RateLimiter, client: &str, now: Instant) -> bool {
+        limiter.take_at(client, now).allowed
+    }
+
+    #[test]
+    fn take_allows_a_burst_then_refills_at_the_rate() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(allowed(&limiter, "a", start));
+        }
+        assert!(!allowed(&limiter, "a", start));
+
+        // one token every half second
+        let taken = limiter.take_at("a", start + Duration::from_millis(400));
+        assert!(!taken.allowed);
+        assert_eq!(limiter.secs_until(taken.remaining, 1.0), 1);
+        assert!(allowed(&limiter, "a", start + Duration::from_millis(500)));
+        assert!(!allowed(&limiter, "a", start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn take_refills_up_to_the_burst() {
+        let limiter = RateLimiter::new(10.0, 2);
+        let start = Instant::now();
+        assert!(allowed(&limiter, "a", start));
+        let later = start + Duration::from_secs(60);
+        let taken = limiter.take_at("a", later);
+        assert!(taken.allowed);
+        assert_eq!(taken.remaining, 1.0);
+        assert!(allowed(&limiter, "a", later));
+        assert!(!allowed(&limiter, "a", later));
+    }
+
+    #[test]
+    fn take_keeps_a_bucket_per_client() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let start = Instant::now();
+        assert!(allowed(&limiter, "a", start));
+        assert!(!allowed(&limiter, "a", start));
+        assert!(allowed(&limiter, "b", start));
+    }
+
+    #[test]
+    fn take_allows_at_least_one_with_a_zero_burst() {
+        let limiter = RateLimiter::new(1.0, 0);
+        let start = Instant::now();
+        assert!(allowed(&limiter, "a", start));
+        assert!(!allowed(&limiter, "a", start));
+    }
+}
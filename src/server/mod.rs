@@ -1,12 +1,39 @@
+pub mod auth;
+pub mod bulk;
+pub mod cache;
 pub mod database;
+pub mod error;
+pub mod etag;
+pub mod groups;
 pub mod health;
+pub mod history;
+pub mod jwt;
+pub mod layers;
+pub mod limits;
+pub mod prometheus;
+pub mod request_id;
+pub mod roles;
+pub mod schema;
+pub mod sessions;
+pub mod tables;
+pub mod validation;
 
 use crate::config::Config;
 
-use crate::db::dispatch::create_pool;
-use axum::Router;
+use crate::db::dispatch::{create_pool, create_query_pool};
+use crate::db::health::MIGRATOR;
+use crate::db::history::History;
+use crate::db::tables::Catalog;
+use crate::server::auth::SecurityAddon;
+use crate::server::cache::QueryCache;
+use crate::server::error::{ApiError, ApiResult, ErrorResponses};
+use crate::server::jwt::JwtValidator;
+use crate::server::limits::Limits;
+use axum::{middleware, Router};
 use sqlx::PgPool;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
@@ -16,6 +43,11 @@ pub struct ServeOpts {
     /// Use own database (to perform migrations)
     #[clap(long)]
     own_db: bool,
+
+    /// Accept raw SQL conditions in the `where` parameter of `GET /api/users`.
+    /// This allows SQL injection, so only use it for local testing.
+    #[clap(long)]
+    unsafe_where: bool,
 }
 
 #[derive(OpenApi)]
@@ -27,7 +59,50 @@ pub struct ServeOpts {
         database::add_user,
         database::update_user,
         database::delete_user,
+        database::get_user,
+        database::get_user_by_email,
+        database::patch_user,
+        database::delete_user_by_id,
+        database::get_user_history,
+        database::restore_user,
+        database::purge_user,
+        database::purge_users,
+        roles::get_roles,
+        roles::add_role,
+        roles::get_role,
+        roles::patch_role,
+        roles::delete_role,
+        roles::get_role_users,
+        roles::add_user_role,
+        roles::remove_user_role,
+        roles::get_user_roles,
+        groups::get_groups,
+        groups::add_group,
+        groups::get_group,
+        groups::patch_group,
+        groups::delete_group,
+        groups::get_members,
+        groups::add_member,
+        groups::remove_member,
+        groups::get_user_groups,
+        sessions::login,
+        sessions::logout,
+        sessions::get_session,
+        sessions::set_password,
+        sessions::reset_password,
+        sessions::get_user_sessions,
+        sessions::revoke_user_sessions,
+        sessions::revoke_session,
+        bulk::import_users,
+        bulk::export_users,
+        history::get_history,
+        schema::get_schemas,
+        schema::get_tables,
+        schema::describe_table,
+        tables::get_table_rows,
         health::get_health,
+        health::get_liveness,
+        health::get_readiness,
         health::ping,
     ),
     components(
@@ -36,22 +111,86 @@ pub struct ServeOpts {
             database::UserRes,
             database::UserPostReq,
             database::UserPutReq,
+            database::UserPatchReq,
             database::UserDeleteReq,
             database::UserDeleteRes,
+            database::PurgeRes,
+            roles::RolePostReq,
+            roles::RolePatchReq,
+            groups::GroupPostReq,
+            groups::GroupPatchReq,
+            crate::models::Role,
+            crate::models::Group,
+            sessions::LoginReq,
+            sessions::LoginRes,
+            sessions::SessionRes,
+            sessions::PasswordPutReq,
+            sessions::PasswordResetRes,
+            sessions::RevokedRes,
+            crate::db::sessions::Session,
+            crate::db::bulk::ImportReport,
+            crate::db::bulk::RowError,
+            crate::db::history::QueryRecord,
+            crate::db::row_history::RowChange,
+            crate::db::schema::SchemaInfo,
+            crate::db::schema::TableInfo,
+            crate::db::schema::ColumnInfo,
+            crate::db::schema::KeyInfo,
+            crate::db::schema::ForeignKeyInfo,
+            crate::db::schema::IndexInfo,
+            crate::db::schema::TableDescription,
+            error::ProblemDetails,
             health::HealthStatus,
+            health::Liveness,
+            health::Readiness,
+            health::ReadinessCheck,
             health::Pong,
         ),
     ),
     tags(
         (name = "database", description = "Database"),
+        (name = "roles", description = "Roles assigned to users"),
+        (name = "groups", description = "Groups of users"),
+        (name = "sessions", description = "Passwords, login, and sessions"),
+        (name = "bulk", description = "Bulk import and export of users"),
+        (name = "history", description = "History of generic queries"),
+        (name = "schema", description = "Database structure"),
+        (name = "tables", description = "Read-only access to any table or view"),
         (name = "health", description = "Basic service status"),
-    )
+    ),
+    modifiers(&ErrorResponses, &SecurityAddon),
 )]
 struct ApiDoc;
 
 #[derive(Clone)]
 pub(crate) struct AppState {
     pool: PgPool,
+    /// For the generic queries run as a `db_role`, if configured.
+    query_pool: Option<PgPool>,
+    history: Arc<History>,
+    cache: Option<Arc<QueryCache>>,
+    limits: Arc<Limits>,
+    catalog: Arc<Catalog>,
+    unsafe_where: bool,
+    /// Whether readiness requires all the migrations applied, as when running them.
+    check_migrations: bool,
+    session_ttl: Duration,
+    jwt: Option<Arc<JwtValidator>>,
+}
+
+impl AppState {
+    /// The pool to run the generic queries of a principal with the given `db_role`.
+    /// Running as a `db_role` needs the query pool, whose user has no privileges of its own,
+    /// as from the service's user the role could be reset to it within the query.
+    fn pool_for(&self, db_role: Option<&str>) -> ApiResult<&PgPool> {
+        match (db_role, &self.query_pool) {
+            (None, _) => Ok(&self.pool),
+            (Some(_), Some(query_pool)) => Ok(query_pool),
+            (Some(db_role), None) => Err(ApiError::internal(format!(
+                "Running as db_role '{db_role}' needs SQLXUM_QUERY_DATABASE_URL"
+            ))),
+        }
+    }
 }
 
 pub async fn launch(opts: &ServeOpts) -> anyhow::Result<()> {
@@ -62,25 +201,70 @@ pub async fn launch(opts: &ServeOpts) -> anyhow::Result<()> {
     let pool = create_pool(&config).await?;
 
     if opts.own_db {
-        sqlx::migrate!("./migrations").run(&pool).await?;
+        MIGRATOR.run(&pool).await?;
     }
 
-    let app_state = AppState { pool: pool.clone() };
+    let app_state = AppState {
+        pool: pool.clone(),
+        query_pool: create_query_pool(&config).await?,
+        history: Arc::new(History::new(
+            config.history_file.clone(),
+            config.history_max_bytes,
+        )),
+        cache: QueryCache::new(&config.cache).map(Arc::new),
+        limits: Arc::new(Limits::new(&config.limits)),
+        catalog: Arc::new(Catalog::load(&pool).await?),
+        unsafe_where: opts.unsafe_where,
+        check_migrations: opts.own_db,
+        session_ttl: config.session_ttl,
+        jwt: JwtValidator::new(&config.jwt)?.map(Arc::new),
+    };
+
+    let mut api = Router::new()
+        .merge(health::create_router(app_state.clone()))
+        .merge(roles::create_router(app_state.clone()))
+        .merge(groups::create_router(app_state.clone()))
+        .merge(sessions::create_router(app_state.clone()))
+        .merge(bulk::create_router(app_state.clone()))
+        .merge(history::create_router(app_state.clone()))
+        .merge(schema::create_router(app_state.clone()))
+        .merge(tables::create_router(app_state.clone()))
+        .merge(database::create_router(app_state.clone()).await?)
+        // after authentication (added below), so limits apply per principal if any
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            limits::rate_limit,
+        ));
+    if config.require_auth || app_state.jwt.is_some() {
+        api = api.layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            auth::authenticate,
+        ));
+    } else {
+        tracing::warn!(
+            "No authentication required (SQLXUM_REQUIRE_AUTH); the API is open to anyone"
+        );
+    }
+    // before authentication, so the requests failing it are limited too
+    let api = api.layer(middleware::from_fn_with_state(
+        app_state,
+        limits::rate_limit_ip,
+    ));
+    // outermost, so the requests rejected by the limits or authentication are counted too
+    let api = api.route_layer(middleware::from_fn(prometheus::track));
 
     let app = Router::new()
-        .nest(
-            "/api",
-            Router::new()
-                .merge(health::create_router())
-                .merge(database::create_router(app_state).await?),
-        )
+        .nest("/api", api)
+        .merge(prometheus::create_router(pool)?)
         .merge(create_swagger_router(&config));
+    let app = layers::add_layers(app, &config.http)?;
 
     let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port));
     let listener = TcpListener::bind(address.to_string()).await?;
     println!("Server listening on {}", address);
 
-    axum::serve(listener, app.into_make_service())
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
         .with_graceful_shutdown(shutdown_signal())
         .await?;
     Ok(())
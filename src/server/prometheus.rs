@@ -0,0 +1,113 @@
+//! Metrics in the Prometheus text format, at `/metrics`: HTTP requests by route and status,
+//! durations and rows of the generic queries, database errors by SQLSTATE class,
+//! and the connections in the pool (with the waiters counted by `db::pool`).
+
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::{routing::get, Router};
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sqlx::PgPool;
+
+const SECONDS_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const ROWS_BUCKETS: [f64; 7] = [0.0, 1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0];
+
+#[derive(Clone)]
+struct MetricsState {
+    handle: PrometheusHandle,
+    pool: PgPool,
+}
+
+/// Installs the global recorder of the metrics, rendered by the returned router.
+pub fn create_router(pool: PgPool) -> anyhow::Result<Router> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), &SECONDS_BUCKETS)?
+        .set_buckets_for_metric(
+            Matcher::Full("sqlxum_query_rows".to_string()),
+            &ROWS_BUCKETS,
+        )?
+        .install_recorder()?;
+    describe();
+    // only changed as counted, so present from the start
+    metrics::gauge!("sqlxum_db_pool_waiters").set(0.0);
+    Ok(Router::new()
+        .route("/metrics", get(render))
+        .with_state(MetricsState { handle, pool }))
+}
+
+fn describe() {
+    describe_counter!("http_requests_total", "HTTP requests handled");
+    describe_histogram!(
+        "http_request_duration_seconds",
+        Unit::Seconds,
+        "Time to handle an HTTP request, until the response head"
+    );
+    describe_histogram!(
+        "sqlxum_query_duration_seconds",
+        Unit::Seconds,
+        "Time to execute a generic query"
+    );
+    describe_histogram!("sqlxum_query_rows", "Rows returned by a generic query");
+    describe_counter!(
+        "sqlxum_db_errors_total",
+        "Database errors in the requests, by SQLSTATE class"
+    );
+    describe_gauge!("sqlxum_db_pool_connections", "Connections in the pool");
+    describe_gauge!(
+        "sqlxum_db_pool_idle_connections",
+        "Connections in the pool not in use"
+    );
+    describe_gauge!(
+        "sqlxum_db_pool_max_connections",
+        "Maximum connections in the pool"
+    );
+    describe_gauge!(
+        "sqlxum_db_pool_waiters",
+        "Tasks waiting for a connection from the pool, for the acquisitions counted"
+    );
+}
+
+/// The metrics, with the pool gauges as of now.
+async fn render(State(state): State<MetricsState>) -> String {
+    let pool = &state.pool;
+    metrics::gauge!("sqlxum_db_pool_connections").set(pool.size() as f64);
+    metrics::gauge!("sqlxum_db_pool_idle_connections").set(pool.num_idle() as f64);
+    metrics::gauge!("sqlxum_db_pool_max_connections")
+        .set(pool.options().get_max_connections() as f64);
+    state.handle.render()
+}
+
+/// Route middleware counting and timing the requests, by method, route
+/// (the path as declared, e.g., `/api/users/:user_id`), and status.
+pub async fn track(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let method = req.method().to_string();
+
+    let res = next.run(req).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", res.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(start.elapsed().as_secs_f64());
+    res
+}
+
+/// Counts a database error, by the class of its SQLSTATE (the first two characters).
+pub fn count_db_error(sqlstate: &str) {
+    let class = sqlstate.get(..2).unwrap_or(sqlstate).to_string();
+    metrics::counter!("sqlxum_db_errors_total", "class" => class).increment(1);
+}
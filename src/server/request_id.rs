@@ -0,0 +1,118 @@
+//! An ID for each request, taken from the `X-Request-Id` header or else generated,
+//! recorded in the span of the request (so in all its log lines and exported spans),
+//! in error bodies, and in the response header. Also, one access log event per request.
+
+use std::time::Instant;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::HeaderValue;
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::StreamExt;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+use crate::server::auth::Principal;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Target of the access log events.
+pub const ACCESS_LOG_TARGET: &str = "sqlxum::access";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// ID of the request being handled by the current task, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// The ID given by the client, if reasonable, to be used as is.
+fn given_id(req: &Request) -> Option<String> {
+    let id = req.headers().get(REQUEST_ID_HEADER)?.to_str().ok()?;
+    let valid = !id.is_empty() && id.len() <= 128 && id.chars().all(|c| c.is_ascii_graphic());
+    valid.then(|| id.to_string())
+}
+
+/// Middleware assigning the request ID, handling the request in its span,
+/// and writing the access log event.
+/// The `principal` of the span is recorded once authenticated (see `auth::authenticate`).
+pub async fn track(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let request_id = given_id(&req).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let span = tracing::info_span!(
+        "request",
+        request_id,
+        method,
+        path,
+        principal = Empty,
+        status = Empty,
+    );
+
+    let mut res = REQUEST_ID
+        .scope(request_id.clone(), next.run(req))
+        .instrument(span.clone())
+        .await;
+    span.record("status", res.status().as_u16());
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let mut entry = AccessLog {
+        start,
+        request_id,
+        method,
+        path,
+        status: res.status().as_u16(),
+        principal: res
+            .extensions()
+            .get::<Principal>()
+            .map(|p| p.subject.clone()),
+        bytes: 0,
+    };
+    // the event is written once the body is sent (or the client goes away)
+    let (parts, body) = res.into_parts();
+    let body = body.into_data_stream().inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            entry.sent(chunk.len());
+        }
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+struct AccessLog {
+    start: Instant,
+    request_id: String,
+    method: String,
+    path: String,
+    status: u16,
+    principal: Option<String>,
+    /// Of the response body, as sent (e.g., compressed).
+    bytes: usize,
+}
+
+impl AccessLog {
+    fn sent(&mut self, bytes: usize) {
+        self.bytes += bytes;
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        tracing::info!(
+            target: ACCESS_LOG_TARGET,
+            request_id = self.request_id,
+            method = self.method,
+            path = self.path,
+            status = self.status,
+            latency_ms = self.start.elapsed().as_secs_f64() * 1000.0,
+            bytes = self.bytes,
+            principal = self.principal,
+            "access"
+        );
+    }
+}
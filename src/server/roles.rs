@@ -0,0 +1,324 @@
+use axum::http::StatusCode;
+use axum::{
+    extract::{Path, State},
+    routing::{get, patch, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::db::roles;
+use crate::models::Permission::{Admin, UsersRead};
+use crate::models::{Permission, Role};
+use crate::server::auth::require;
+use crate::server::database::{existing_user, UserRes};
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::validation::{double_option, normalize_str, Normalize, ValidatedJson};
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route(
+            "/roles",
+            get(get_roles)
+                .route_layer(require(UsersRead))
+                .merge(post(add_role).route_layer(require(Admin))),
+        )
+        .route(
+            "/roles/:role_id",
+            get(get_role).route_layer(require(UsersRead)).merge(
+                patch(patch_role)
+                    .delete(delete_role)
+                    .route_layer(require(Admin)),
+            ),
+        )
+        .route(
+            "/roles/:role_id/users",
+            get(get_role_users).route_layer(require(UsersRead)),
+        )
+        .route(
+            "/roles/:role_id/users/:user_id",
+            put(add_user_role)
+                .delete(remove_user_role)
+                .route_layer(require(Admin)),
+        )
+        .route(
+            "/users/:user_id/roles",
+            get(get_user_roles).route_layer(require(UsersRead)),
+        )
+        .with_state(app_state)
+}
+
+/// Request content to add a role
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
+pub struct RolePostReq {
+    /// Name of the role, e.g., `operator`
+    #[schema(min_length = 1, max_length = 64)]
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: String,
+    #[schema(max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub description: Option<String>,
+    /// Permissions of the principals with the role
+    #[serde(default)]
+    pub permissions: Vec<Permission>,
+    /// Postgres role for the generic queries of the principals with the role.
+    /// The database user of the service must be a member of it.
+    #[schema(min_length = 1, max_length = 63)]
+    #[validate(length(min = 1, max = 63, message = "must have between 1 and 63 characters"))]
+    pub db_role: Option<String>,
+}
+
+impl Normalize for RolePostReq {
+    fn normalize(&mut self) {
+        normalize_str(&mut self.name, false);
+    }
+}
+
+/// Request content to patch a role. Only the given fields are updated.
+#[derive(Serialize, Deserialize, ToSchema, Validate, Clone, Debug)]
+pub struct RolePatchReq {
+    #[schema(min_length = 1, max_length = 64)]
+    #[validate(length(min = 1, max = 64, message = "must have between 1 and 64 characters"))]
+    pub name: Option<String>,
+    #[schema(max_length = 1000)]
+    #[validate(length(max = 1000, message = "must be at most 1000 characters"))]
+    pub description: Option<String>,
+    /// Replaces the permissions of the role
+    pub permissions: Option<Vec<Permission>>,
+    /// `null` to clear it
+    #[schema(value_type = Option<String>, nullable, min_length = 1, max_length = 63)]
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(length(min = 1, max = 63, message = "must have between 1 and 63 characters"))]
+    pub db_role: Option<Option<String>>,
+}
+
+impl Normalize for RolePatchReq {
+    fn normalize(&mut self) {
+        if let Some(name) = &mut self.name {
+            normalize_str(name, false);
+        }
+    }
+}
+
+/// Get all roles
+#[utoipa::path(
+    get,
+    path = "/roles",
+    responses(
+       (status = 200, description = "List of roles", body = Vec<Role>)
+    )
+)]
+pub async fn get_roles(state: State<AppState>) -> ApiResult<Json<Vec<Role>>> {
+    tracing::info!("get_roles");
+    Ok(Json(roles::get_roles(&state.pool).await?))
+}
+
+/// Add a role
+#[utoipa::path(
+    post,
+    path = "/roles",
+    request_body = RolePostReq,
+    responses(
+       (status = 200, description = "Role added", body = Role),
+       (status = 409, description = "Name already taken", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn add_role(
+    state: State<AppState>,
+    ValidatedJson(req): ValidatedJson<RolePostReq>,
+) -> ApiResult<Json<Role>> {
+    tracing::info!("add_role: {req:?}");
+    let role = roles::insert_role(
+        &state.pool,
+        &req.name,
+        req.description.as_deref(),
+        &permission_names(&req.permissions),
+        req.db_role.as_deref(),
+    )
+    .await?;
+    Ok(Json(role))
+}
+
+/// Get a role
+#[utoipa::path(
+    get,
+    path = "/roles/{role_id}",
+    params(("role_id" = String, Path, description = "ID of the role")),
+    responses(
+       (status = 200, description = "The role", body = Role),
+       (status = 404, description = "Role not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_role(
+    state: State<AppState>,
+    Path(role_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Role>> {
+    tracing::info!("get_role: {role_id}");
+    Ok(Json(existing_role(&state.pool, &role_id).await?))
+}
+
+/// Update some fields of a role
+#[utoipa::path(
+    patch,
+    path = "/roles/{role_id}",
+    params(("role_id" = String, Path, description = "ID of the role")),
+    request_body = RolePatchReq,
+    responses(
+       (status = 200, description = "Role updated", body = Role),
+       (status = 404, description = "Role not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn patch_role(
+    state: State<AppState>,
+    Path(role_id): Path<uuid::Uuid>,
+    ValidatedJson(req): ValidatedJson<RolePatchReq>,
+) -> ApiResult<Json<Role>> {
+    tracing::info!("patch_role: {role_id} {req:?}");
+    let (name, description) = (req.name.as_deref(), req.description.as_deref());
+    let permissions = req.permissions.as_deref().map(permission_names);
+    let db_role = req.db_role.as_ref().map(Option::as_deref);
+    match roles::update_role(
+        &state.pool,
+        &role_id,
+        name,
+        description,
+        permissions.as_deref(),
+        db_role,
+    )
+    .await?
+    {
+        Some(role) => Ok(Json(role)),
+        None => Err(role_not_found(&role_id)),
+    }
+}
+
+/// Delete a role, unassigning it from all users
+#[utoipa::path(
+    delete,
+    path = "/roles/{role_id}",
+    params(("role_id" = String, Path, description = "ID of the role")),
+    responses(
+       (status = 204, description = "Role deleted"),
+       (status = 404, description = "Role not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn delete_role(
+    state: State<AppState>,
+    Path(role_id): Path<uuid::Uuid>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("delete_role: {role_id}");
+    match roles::delete_role(&state.pool, &role_id).await? {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err(role_not_found(&role_id)),
+    }
+}
+
+/// Get the users with a role
+#[utoipa::path(
+    get,
+    path = "/roles/{role_id}/users",
+    params(("role_id" = String, Path, description = "ID of the role")),
+    responses(
+       (status = 200, description = "Users with the role", body = Vec<UserRes>),
+       (status = 404, description = "Role not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_role_users(
+    state: State<AppState>,
+    Path(role_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<UserRes>>> {
+    tracing::info!("get_role_users: {role_id}");
+    let pool = &state.pool;
+    existing_role(pool, &role_id).await?;
+    let users = roles::get_role_users(pool, &role_id).await?;
+    Ok(Json(users.iter().map(UserRes::from_user).collect()))
+}
+
+/// Assign a role to a user
+#[utoipa::path(
+    put,
+    path = "/roles/{role_id}/users/{user_id}",
+    params(
+        ("role_id" = String, Path, description = "ID of the role"),
+        ("user_id" = String, Path, description = "ID of the user"),
+    ),
+    responses(
+       (status = 204, description = "Role assigned (or already assigned)"),
+       (status = 404, description = "Role or user not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn add_user_role(
+    state: State<AppState>,
+    Path((role_id, user_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("add_user_role: {role_id} {user_id}");
+    let pool = &state.pool;
+    existing_role(pool, &role_id).await?;
+    existing_user(pool, &user_id).await?;
+    roles::add_user_role(pool, &user_id, &role_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Unassign a role from a user
+#[utoipa::path(
+    delete,
+    path = "/roles/{role_id}/users/{user_id}",
+    params(
+        ("role_id" = String, Path, description = "ID of the role"),
+        ("user_id" = String, Path, description = "ID of the user"),
+    ),
+    responses(
+       (status = 204, description = "Role unassigned"),
+       (status = 404, description = "Role not assigned to the user", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn remove_user_role(
+    state: State<AppState>,
+    Path((role_id, user_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("remove_user_role: {role_id} {user_id}");
+    if roles::remove_user_role(&state.pool, &user_id, &role_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!(
+            "Role {role_id} not assigned to user {user_id}"
+        )))
+    }
+}
+
+/// Get the roles of a user
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/roles",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Roles of the user", body = Vec<Role>),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user_roles(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<Role>>> {
+    tracing::info!("get_user_roles: {user_id}");
+    let pool = &state.pool;
+    existing_user(pool, &user_id).await?;
+    Ok(Json(roles::get_user_roles(pool, &user_id).await?))
+}
+
+async fn existing_role(pool: &sqlx::PgPool, role_id: &uuid::Uuid) -> ApiResult<Role> {
+    match roles::get_role(pool, role_id).await? {
+        Some(role) => Ok(role),
+        None => Err(role_not_found(role_id)),
+    }
+}
+
+fn role_not_found(role_id: &uuid::Uuid) -> ApiError {
+    ApiError::not_found(format!("Role not found: {role_id}"))
+}
+
+fn permission_names(permissions: &[Permission]) -> Vec<String> {
+    permissions.iter().map(|p| p.as_str().to_string()).collect()
+}
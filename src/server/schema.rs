@@ -0,0 +1,83 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::db::schema;
+use crate::models::Permission;
+use crate::server::auth::require;
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/schema", get(get_schemas))
+        .route("/schema/tables", get(get_tables))
+        .route("/schema/tables/:schema/:table", get(describe_table))
+        .route_layer(require(Permission::Query))
+        .with_state(app_state)
+}
+
+/// List the (non-system) schemas in the database.
+#[utoipa::path(
+    get,
+    path = "/schema",
+    responses(
+       (status = 200, description = "List of schemas", body = Vec<schema::SchemaInfo>)
+    )
+)]
+pub async fn get_schemas(state: State<AppState>) -> ApiResult<Json<Vec<schema::SchemaInfo>>> {
+    tracing::info!("get_schemas");
+    Ok(Json(schema::get_schemas(&state.pool).await?))
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct TablesParams {
+    /// Only tables in this schema.
+    schema: Option<String>,
+}
+
+/// List tables and views.
+#[utoipa::path(
+    get,
+    path = "/schema/tables",
+    params(TablesParams),
+    responses(
+       (status = 200, description = "List of tables and views", body = Vec<schema::TableInfo>)
+    )
+)]
+pub async fn get_tables(
+    state: State<AppState>,
+    Query(params): Query<TablesParams>,
+) -> ApiResult<Json<Vec<schema::TableInfo>>> {
+    tracing::info!("get_tables: {params:?}");
+    let res = schema::get_tables(&state.pool, params.schema.as_deref()).await?;
+    Ok(Json(res))
+}
+
+/// Describe a table or view: columns, keys, and indexes.
+#[utoipa::path(
+    get,
+    path = "/schema/tables/{schema}/{table}",
+    params(
+        ("schema" = String, Path, description = "Schema name"),
+        ("table" = String, Path, description = "Table name"),
+    ),
+    responses(
+       (status = 200, description = "Table description", body = schema::TableDescription),
+       (status = 404, description = "Table not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn describe_table(
+    state: State<AppState>,
+    Path((schema, table)): Path<(String, String)>,
+) -> ApiResult<Json<schema::TableDescription>> {
+    tracing::info!("describe_table: {schema}.{table}");
+    match schema::describe_table(&state.pool, &schema, &table).await? {
+        Some(res) => Ok(Json(res)),
+        None => Err(ApiError::not_found(format!("{schema}.{table} not found"))),
+    }
+}
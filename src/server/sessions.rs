@@ -0,0 +1,410 @@
+use std::net::SocketAddr;
+
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::common::new_token;
+use crate::db::sessions::{self, Session};
+use crate::db::{passwords, roles, users};
+use crate::models::Permission::{Admin, UsersRead, UsersWrite};
+use crate::server::auth::{require, Principal};
+use crate::server::database::{existing_user, UserRes};
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::validation::{normalize_str, Normalize, ValidatedJson};
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/login", post(login))
+        .route("/logout", post(logout))
+        .route("/session", get(get_session))
+        .route("/users/:user_id/password", put(set_password))
+        .route(
+            "/users/:user_id/password/reset",
+            post(reset_password).route_layer(require(UsersWrite)),
+        )
+        .route(
+            "/users/:user_id/sessions",
+            get(get_user_sessions)
+                .route_layer(require(UsersRead))
+                .merge(delete(revoke_user_sessions).route_layer(require(UsersWrite))),
+        )
+        .route(
+            "/users/:user_id/sessions/:session_id",
+            delete(revoke_session).route_layer(require(UsersWrite)),
+        )
+        .with_state(app_state)
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+pub struct LoginReq {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct LoginRes {
+    /// To be passed as `Authorization: Bearer <token>`. Only given here.
+    pub token: String,
+    pub session: Session,
+    pub user: UserRes,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct SessionRes {
+    pub session: Session,
+    pub user: UserRes,
+}
+
+/// Log in with email and password
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = LoginReq,
+    responses(
+       (status = 200, description = "Session created", body = LoginRes),
+       (status = 401, description = "Invalid email or password", body = crate::server::error::ProblemDetails),
+       (status = 429, description = "Too many login attempts for the email", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn login(
+    state: State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(mut req): Json<LoginReq>,
+) -> ApiResult<Response> {
+    normalize_str(&mut req.email, true);
+    let email = req.email;
+    tracing::info!("login: {email}");
+    if let Some(res) = state.limits.throttle_login(&email, &headers, addr.ip()) {
+        return Ok(res);
+    }
+    let pool = &state.pool;
+    let invalid = || ApiError::new(StatusCode::UNAUTHORIZED, "Invalid email or password");
+
+    // without a user or password, the dummy hash is verified all the same,
+    // so the time taken does not tell whether they exist
+    let user = users::get_user_by_email(pool, &email).await?;
+    let hash = match &user {
+        Some(user) => passwords::get_password_hash(pool, &user.user_id).await?,
+        None => None,
+    };
+    let found = hash.is_some();
+    let password = req.password;
+    let valid = tokio::task::spawn_blocking(move || {
+        let hash = match &hash {
+            Some(hash) => hash,
+            None => passwords::dummy_hash(),
+        };
+        passwords::verify_password(&password, hash)
+    })
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?;
+    let user = match user {
+        Some(user) if found && valid => user,
+        _ => return Err(invalid()),
+    };
+
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok());
+    let ip = addr.ip().to_string();
+    let (session, token) = sessions::create_session(
+        pool,
+        &user.user_id,
+        state.session_ttl,
+        user_agent,
+        Some(&ip),
+    )
+    .await?;
+    Ok(Json(LoginRes {
+        token,
+        session,
+        user: UserRes::from_user(&user),
+    })
+    .into_response())
+}
+
+/// Log out, revoking the session given by the bearer token
+#[utoipa::path(
+    post,
+    path = "/logout",
+    responses(
+       (status = 204, description = "Session revoked"),
+       (status = 401, description = "No valid session", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn logout(state: State<AppState>, headers: HeaderMap) -> ApiResult<StatusCode> {
+    let session = current_session(&state.pool, &headers).await?;
+    tracing::info!("logout: {}", session.session_id);
+    sessions::revoke_session(&state.pool, &session.user_id, &session.session_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Get the session given by the bearer token, along with its user
+#[utoipa::path(
+    get,
+    path = "/session",
+    responses(
+       (status = 200, description = "The session", body = SessionRes),
+       (status = 401, description = "No valid session", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_session(
+    state: State<AppState>,
+    headers: HeaderMap,
+) -> ApiResult<Json<SessionRes>> {
+    let pool = &state.pool;
+    let session = current_session(pool, &headers).await?;
+    let user = existing_user(pool, &session.user_id).await?;
+    Ok(Json(SessionRes {
+        session,
+        user: UserRes::from_user(&user),
+    }))
+}
+
+/// Minimum length of a password.
+const PASSWORD_MIN_LEN: u64 = 8;
+
+/// Request content to set the password of a user
+#[derive(Deserialize, ToSchema, Validate, Debug)]
+pub struct PasswordPutReq {
+    /// Required if the user already has a password.
+    pub current_password: Option<String>,
+    #[schema(min_length = 8, max_length = 128)]
+    #[validate(length(
+        min = "PASSWORD_MIN_LEN",
+        max = 128,
+        message = "must have between 8 and 128 characters"
+    ))]
+    pub new_password: String,
+}
+
+impl Normalize for PasswordPutReq {
+    fn normalize(&mut self) {
+        // passwords are taken as given
+    }
+}
+
+/// Set the password of a user
+///
+/// Users can set their own password; otherwise `users:write` is required.
+/// If the user has no password yet, only the user or an admin can set it.
+/// All the sessions of the user are revoked.
+#[utoipa::path(
+    put,
+    path = "/users/{user_id}/password",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    request_body = PasswordPutReq,
+    responses(
+       (status = 204, description = "Password set"),
+       (status = 403, description = "Current password missing or incorrect, or not allowed to set it", body = crate::server::error::ProblemDetails),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn set_password(
+    state: State<AppState>,
+    principal: Option<Principal>,
+    Path(user_id): Path<uuid::Uuid>,
+    ValidatedJson(req): ValidatedJson<PasswordPutReq>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("set_password: {user_id}");
+    if let Some(principal) = &principal {
+        if !principal.is_user(&user_id) && !principal.has_permission(UsersWrite) {
+            tracing::info!("set_password: {} not the user", principal.subject);
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Missing the 'users:write' permission to set the password of another user",
+            ));
+        }
+    }
+    let pool = &state.pool;
+    existing_user(pool, &user_id).await?;
+
+    if let Some(hash) = passwords::get_password_hash(pool, &user_id).await? {
+        let current = req.current_password.unwrap_or_default();
+        let valid =
+            tokio::task::spawn_blocking(move || passwords::verify_password(&current, &hash))
+                .await
+                .map_err(|e| ApiError::internal(e.to_string()))?;
+        if !valid {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Current password missing or incorrect",
+            ));
+        }
+    } else if let Some(principal) = principal {
+        if !principal.is_user(&user_id) && !principal.has_permission(Admin) {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Only the user or an admin can set the first password",
+            ));
+        }
+    }
+    store_password(pool, &user_id, req.new_password).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct PasswordResetRes {
+    /// New temporary password, to be changed by the user.
+    pub password: String,
+}
+
+/// Reset the password of a user to a random one
+///
+/// Only an admin can reset the password of an admin.
+/// All the sessions of the user are revoked.
+#[utoipa::path(
+    post,
+    path = "/users/{user_id}/password/reset",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Password reset", body = PasswordResetRes),
+       (status = 403, description = "Not allowed to reset the password of an admin", body = crate::server::error::ProblemDetails),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn reset_password(
+    state: State<AppState>,
+    principal: Option<Principal>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<PasswordResetRes>> {
+    tracing::info!("reset_password: {user_id}");
+    let pool = &state.pool;
+    existing_user(pool, &user_id).await?;
+    if let Some(principal) = principal.filter(|p| !p.has_permission(Admin)) {
+        if is_admin_user(pool, &user_id).await? {
+            tracing::info!("reset_password: {} not an admin", principal.subject);
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Only an admin can reset the password of an admin",
+            ));
+        }
+    }
+    let password = new_token()[..16].to_string();
+    store_password(pool, &user_id, password.clone()).await?;
+    Ok(Json(PasswordResetRes { password }))
+}
+
+/// Get the active sessions of a user, most recent first
+#[utoipa::path(
+    get,
+    path = "/users/{user_id}/sessions",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Sessions of the user", body = Vec<Session>),
+       (status = 404, description = "User not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_user_sessions(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<Vec<Session>>> {
+    tracing::info!("get_user_sessions: {user_id}");
+    let pool = &state.pool;
+    existing_user(pool, &user_id).await?;
+    Ok(Json(sessions::get_user_sessions(pool, &user_id).await?))
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+pub struct RevokedRes {
+    /// Number of sessions revoked.
+    pub revoked: u64,
+}
+
+/// Revoke all the sessions of a user
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/sessions",
+    params(("user_id" = String, Path, description = "ID of the user")),
+    responses(
+       (status = 200, description = "Sessions revoked", body = RevokedRes)
+    )
+)]
+pub async fn revoke_user_sessions(
+    state: State<AppState>,
+    Path(user_id): Path<uuid::Uuid>,
+) -> ApiResult<Json<RevokedRes>> {
+    tracing::info!("revoke_user_sessions: {user_id}");
+    let revoked = sessions::revoke_user_sessions(&state.pool, &user_id).await?;
+    Ok(Json(RevokedRes { revoked }))
+}
+
+/// Revoke a session of a user
+#[utoipa::path(
+    delete,
+    path = "/users/{user_id}/sessions/{session_id}",
+    params(
+        ("user_id" = String, Path, description = "ID of the user"),
+        ("session_id" = String, Path, description = "ID of the session"),
+    ),
+    responses(
+       (status = 204, description = "Session revoked"),
+       (status = 404, description = "Session not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn revoke_session(
+    state: State<AppState>,
+    Path((user_id, session_id)): Path<(uuid::Uuid, uuid::Uuid)>,
+) -> ApiResult<StatusCode> {
+    tracing::info!("revoke_session: {user_id} {session_id}");
+    if sessions::revoke_session(&state.pool, &user_id, &session_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!(
+            "Session not found: {session_id}"
+        )))
+    }
+}
+
+/// Hashes and stores the password, and revokes the sessions of the user.
+async fn store_password(
+    pool: &sqlx::PgPool,
+    user_id: &uuid::Uuid,
+    password: String,
+) -> ApiResult<()> {
+    let hash = tokio::task::spawn_blocking(move || passwords::hash_password(&password))
+        .await
+        .map_err(|e| ApiError::internal(e.to_string()))??;
+    passwords::set_password_hash(pool, user_id, &hash).await?;
+    let revoked = sessions::revoke_user_sessions(pool, user_id).await?;
+    tracing::info!("store_password: {user_id}: {revoked} sessions revoked");
+    Ok(())
+}
+
+/// Whether the user has a role granting `admin`, or the `admin` role
+/// of the row-level security policies.
+async fn is_admin_user(pool: &sqlx::PgPool, user_id: &uuid::Uuid) -> anyhow::Result<bool> {
+    let roles = roles::get_user_roles(pool, user_id).await?;
+    Ok(roles.iter().any(|role| {
+        role.name == Admin.as_str() || role.permissions.iter().any(|p| p == Admin.as_str())
+    }))
+}
+
+/// The token in the `Authorization: Bearer <token>` header, if any.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// The session given by the bearer token, or a 401 error.
+async fn current_session(pool: &sqlx::PgPool, headers: &HeaderMap) -> ApiResult<Session> {
+    let unauthorized = || ApiError::new(StatusCode::UNAUTHORIZED, "No valid session");
+    let token = bearer_token(headers).ok_or_else(unauthorized)?;
+    sessions::get_session_by_token(pool, token)
+        .await?
+        .ok_or_else(unauthorized)
+}
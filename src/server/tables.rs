@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Path, Query, State},
+    middleware,
+    routing::get,
+    Json, Router,
+};
+use serde_json::Value;
+
+use crate::db::tables::{self, TableQuery};
+use crate::models::Permission;
+use crate::server::auth::{require, Principal};
+use crate::server::error::{ApiError, ApiResult};
+use crate::server::limits::limit_queries;
+use crate::server::AppState;
+
+pub fn create_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/tables/:schema/:table", get(get_table_rows))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            limit_queries,
+        ))
+        .route_layer(require(Permission::Query))
+        .with_state(app_state)
+}
+
+/// Get rows from any table or view in the database (other than those with credentials).
+///
+/// As the generic queries, it runs as the `db_role` of the caller, if any,
+/// and with its identity for the row-level security policies.
+///
+/// Besides the parameters below, rows can be filtered with `<column>=[not.]<op>.<value>`,
+/// where `<op>` is one of `eq`, `neq`, `gt`, `gte`, `lt`, `lte`, `like`, `ilike`
+/// (with `*` as wildcard), `in` (e.g., `in.(a,b)`), or `is` (`null`, `true`, `false`).
+/// Multiple filters are combined with `and`.
+/// Conditions can also be grouped, e.g., `or=(name.eq.Foo,and(age.gt.20,age.lt.30))`.
+#[utoipa::path(
+    get,
+    path = "/tables/{schema}/{table}",
+    params(
+        ("schema" = String, Path, description = "Schema name"),
+        ("table" = String, Path, description = "Table name"),
+        ("select" = Option<String>, Query, description = "Comma-separated columns to report. By default, all."),
+        ("order" = Option<String>, Query, description = "Comma-separated columns to order by, each optionally with `.asc` or `.desc`."),
+        ("limit" = Option<u32>, Query, description = "Maximum number of rows. By default, 20."),
+        ("offset" = Option<u32>, Query, description = "Number of rows to skip."),
+    ),
+    responses(
+       (status = 200, description = "Rows of the table", body = Value),
+       (status = 404, description = "Table not found", body = crate::server::error::ProblemDetails)
+    )
+)]
+pub async fn get_table_rows(
+    state: State<AppState>,
+    Path((schema, table)): Path<(String, String)>,
+    principal: Option<Principal>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> ApiResult<Json<Value>> {
+    tracing::info!("get_table_rows: {schema}.{table} {params:?}");
+    let Some(columns) = state.catalog.columns(&schema, &table) else {
+        return Err(ApiError::not_found(format!("{schema}.{table} not found")));
+    };
+    let tq = TableQuery::from_params(&params)?;
+    let db_role = principal.as_ref().and_then(|p| p.db_role.as_deref());
+    let pool = state.pool_for(db_role)?;
+    let res = tables::query_table(pool, db_role, &schema, &table, columns, &tq).await?;
+    Ok(Json(res))
+}
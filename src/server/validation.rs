@@ -0,0 +1,72 @@
+use axum::extract::{FromRequest, Request};
+use axum::{async_trait, Json};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use validator::{Validate, ValidationErrors};
+
+use crate::server::error::ApiError;
+
+/// Adjustments applied to a request before validating it, like trimming.
+pub trait Normalize {
+    fn normalize(&mut self);
+}
+
+/// Like `Json`, but the value is normalized and validated,
+/// with any failures reported per field.
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Normalize + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(mut value) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+        value.normalize();
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// For an optional field that can also be `null`, with `#[serde(default)]`:
+/// `None` if absent (unchanged), and `Some(None)` if `null` (cleared).
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Trims the string and, if `lowercase`, converts it to lowercase.
+pub fn normalize_str(s: &mut String, lowercase: bool) {
+    let trimmed = s.trim();
+    *s = if lowercase {
+        trimmed.to_lowercase()
+    } else {
+        trimmed.to_string()
+    };
+}
+
+/// The messages of the validation failures, by field.
+pub fn field_errors(e: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    e.field_errors()
+        .into_iter()
+        .map(|(field, errs)| {
+            let messages = errs
+                .iter()
+                .map(|err| match &err.message {
+                    Some(message) => message.to_string(),
+                    None => err.code.to_string(),
+                })
+                .collect();
+            (field.to_string(), messages)
+        })
+        .collect()
+}
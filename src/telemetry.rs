@@ -0,0 +1,63 @@
+//! Logging and tracing with `tracing`: a span per HTTP request (see `server::request_id`)
+//! and per SQL statement, written to stderr as text, pretty, or JSON,
+//! and optionally exported with OTLP to a collector.
+//!
+//! The events of the `log` crate (e.g., from dependencies) are included as well.
+
+use std::io::IsTerminal;
+
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{runtime, trace, Resource};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{LogFormat, TelemetryConfig};
+
+/// Installs the global subscriber, filtered per `RUST_LOG` (by default, only errors).
+pub fn init(config: &TelemetryConfig) -> anyhow::Result<()> {
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let fmt = match config.log_format {
+        LogFormat::Text => fmt.boxed(),
+        LogFormat::Pretty => fmt.pretty().boxed(),
+        LogFormat::Json => fmt.json().flatten_event(true).boxed(),
+    };
+
+    let otel = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let tracer = opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new(
+                    "service.name",
+                    config.service_name.clone(),
+                )])))
+                .install_batch(runtime::Tokio)?;
+            Some(tracing_opentelemetry::layer().with_tracer(tracer))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt)
+        .with(otel)
+        .try_init()?;
+
+    if let Some(endpoint) = &config.otlp_endpoint {
+        tracing::info!("Exporting spans to {endpoint}");
+    }
+    Ok(())
+}
+
+/// Exports the spans not exported yet, if any.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}